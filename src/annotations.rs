@@ -0,0 +1,92 @@
+use ggez::graphics;
+
+use crate::cords_to_square;
+
+// right click annotations. from == to is a marked square, anything else is an arrow
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Annotation {
+    pub from: (usize, usize),
+    pub to: (usize, usize),
+    pub color: AnnotationColor,
+}
+
+// same colors and modifier keys as lichess
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AnnotationColor {
+    Green,
+    Red,
+    Blue,
+    Yellow,
+}
+
+impl AnnotationColor {
+    pub fn from_modifiers(shift: bool, ctrl: bool, alt: bool) -> Self {
+        match (shift, ctrl, alt) {
+            (true, _, true) | (_, true, true) => AnnotationColor::Yellow,
+            (true, _, _) => AnnotationColor::Red,
+            (_, _, true) => AnnotationColor::Blue,
+            (_, true, _) => AnnotationColor::Yellow,
+            _ => AnnotationColor::Green,
+        }
+    }
+
+    pub fn rgba(&self) -> graphics::Color {
+        match self {
+            AnnotationColor::Green => graphics::Color::new(0.08, 0.47, 0.11, 0.8),
+            AnnotationColor::Red => graphics::Color::new(0.53, 0.0, 0.0, 0.8),
+            AnnotationColor::Blue => graphics::Color::new(0.0, 0.19, 0.53, 0.8),
+            AnnotationColor::Yellow => graphics::Color::new(0.9, 0.63, 0.06, 0.8),
+        }
+    }
+
+    fn pgn_letter(&self) -> char {
+        match self {
+            AnnotationColor::Green => 'G',
+            AnnotationColor::Red => 'R',
+            AnnotationColor::Blue => 'B',
+            AnnotationColor::Yellow => 'Y',
+        }
+    }
+}
+
+// adds the annotation, or removes it if the exact same one is already there
+pub fn toggle(annotations: &mut Vec<Annotation>, annotation: Annotation) {
+    let existing = annotations.iter().position(|a| a.from == annotation.from && a.to == annotation.to);
+
+    match existing {
+        Some(i) if annotations[i].color == annotation.color => {
+            annotations.remove(i);
+        },
+        Some(i) => annotations[i] = annotation,
+        None => annotations.push(annotation),
+    }
+}
+
+// the [%csl ...] and [%cal ...] comment commands understood by lichess and chessbase
+pub fn pgn_comment(annotations: &[Annotation]) -> Option<String> {
+    let square = |(x, y): (usize, usize)| cords_to_square(x as f32, y as f32);
+
+    let squares: Vec<String> = annotations.iter()
+        .filter(|a| a.from == a.to)
+        .map(|a| format!("{}{}", a.color.pgn_letter(), square(a.from)))
+        .collect();
+    let arrows: Vec<String> = annotations.iter()
+        .filter(|a| a.from != a.to)
+        .map(|a| format!("{}{}{}", a.color.pgn_letter(), square(a.from), square(a.to)))
+        .collect();
+
+    let mut commands = vec![];
+    if !squares.is_empty() {
+        commands.push(format!("[%csl {}]", squares.join(",")));
+    }
+    if !arrows.is_empty() {
+        commands.push(format!("[%cal {}]", arrows.join(",")));
+    }
+
+    if commands.is_empty() {
+        None
+    }
+    else {
+        Some(commands.join(" "))
+    }
+}
@@ -0,0 +1,44 @@
+use crate::server::UniversalGame;
+use crate::rules::Rules;
+use crate::{redkar_chess_utils, fritiofr_chess_utils, erikfran_chess_utils};
+
+// every backend the server can run, the setup screen and the command line modes list them from
+// here. a new backend only needs its line added
+pub const BACKENDS: &[Backend] = &[
+    Backend::register::<redkar_chess_utils::Game>("redkar"),
+    Backend::register::<fritiofr_chess_utils::Game>("fritiofr"),
+    Backend::register::<erikfran_chess_utils::Game>("erikfran"),
+];
+
+// the backends hold their game through the crate they wrap, so a game is made on the thread that
+// plays it instead of being sent there
+#[derive(Clone, Copy)]
+pub struct Backend {
+    pub name: &'static str,
+    // the backend on its own, for comparing backends
+    pub new: fn() -> Box<dyn UniversalGame>,
+    // with the draws the backends don't report, the way the server plays
+    pub with_rules: fn() -> Box<dyn UniversalGame>,
+}
+
+impl Backend {
+    const fn register<G: UniversalGame + 'static>(name: &'static str) -> Self {
+        Backend {
+            name,
+            new: boxed::<G>,
+            with_rules: boxed::<Rules<G>>,
+        }
+    }
+}
+
+fn boxed<G: UniversalGame + 'static>() -> Box<dyn UniversalGame> {
+    Box::new(G::new())
+}
+
+pub fn find(name: &str) -> Option<&'static Backend> {
+    BACKENDS.iter().find(|b| b.name == name)
+}
+
+pub fn names() -> Vec<&'static str> {
+    BACKENDS.iter().map(|b| b.name).collect()
+}
@@ -0,0 +1,369 @@
+use chess_network_protocol::*;
+
+use crate::{piece_color, oposite_color, queen};
+
+// helpers that only look at the network board so they work the same for every backend,
+// even the ones that can't generate moves (redkar)
+
+const KNIGHT_OFFSETS: [(i32, i32); 8] = [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)];
+const KING_OFFSETS: [(i32, i32); 8] = [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)];
+const ROOK_DIRECTIONS: [(i32, i32); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+const BISHOP_DIRECTIONS: [(i32, i32); 4] = [(1, 1), (-1, 1), (-1, -1), (1, -1)];
+
+pub fn on_board(x: i32, y: i32) -> bool {
+    (0..8).contains(&x) && (0..8).contains(&y)
+}
+
+// the direction pawns of this color walk in, white starts on y = 0
+pub fn pawn_direction(color: &Color) -> i32 {
+    match color {
+        Color::White => 1,
+        Color::Black => -1,
+    }
+}
+
+// every square the piece on (x, y) attacks, ignoring whether moving there would be legal
+pub fn attacks_from(board: &[[Piece; 8]; 8], x: usize, y: usize) -> Vec<(usize, usize)> {
+    let piece = board[y][x];
+    let color = match piece_color(&piece) {
+        Some(c) => c,
+        None => return vec![],
+    };
+    let (x, y) = (x as i32, y as i32);
+
+    match piece {
+        Piece::WhitePawn | Piece::BlackPawn => {
+            let dy = pawn_direction(&color);
+            jump(x, y, &[(1, dy), (-1, dy)])
+        },
+        Piece::WhiteKnight | Piece::BlackKnight => jump(x, y, &KNIGHT_OFFSETS),
+        Piece::WhiteKing | Piece::BlackKing => jump(x, y, &KING_OFFSETS),
+        Piece::WhiteRook | Piece::BlackRook => slide(board, x, y, &ROOK_DIRECTIONS),
+        Piece::WhiteBishop | Piece::BlackBishop => slide(board, x, y, &BISHOP_DIRECTIONS),
+        Piece::WhiteQueen | Piece::BlackQueen => {
+            let mut squares = slide(board, x, y, &ROOK_DIRECTIONS);
+            squares.append(&mut slide(board, x, y, &BISHOP_DIRECTIONS));
+            squares
+        },
+        Piece::None => vec![],
+    }
+}
+
+fn jump(x: i32, y: i32, offsets: &[(i32, i32)]) -> Vec<(usize, usize)> {
+    offsets.iter()
+        .filter(|(dx, dy)| on_board(x + dx, y + dy))
+        .map(|(dx, dy)| ((x + dx) as usize, (y + dy) as usize))
+        .collect()
+}
+
+fn slide(board: &[[Piece; 8]; 8], x: i32, y: i32, directions: &[(i32, i32)]) -> Vec<(usize, usize)> {
+    let mut squares = vec![];
+
+    for (dx, dy) in directions {
+        let (mut cx, mut cy) = (x + dx, y + dy);
+
+        while on_board(cx, cy) {
+            squares.push((cx as usize, cy as usize));
+
+            if board[cy as usize][cx as usize] != Piece::None {
+                break;
+            }
+
+            cx += dx;
+            cy += dy;
+        }
+    }
+
+    squares
+}
+
+// all squares attacked by the pieces of the given color
+pub fn attack_set(board: &[[Piece; 8]; 8], color: &Color) -> [[bool; 8]; 8] {
+    let mut attacked = [[false; 8]; 8];
+
+    for y in 0..8 {
+        for x in 0..8 {
+            if piece_color(&board[y][x]).as_ref() == Some(color) {
+                for (ax, ay) in attacks_from(board, x, y) {
+                    attacked[ay][ax] = true;
+                }
+            }
+        }
+    }
+
+    attacked
+}
+
+pub fn king_position(board: &[[Piece; 8]; 8], color: &Color) -> Option<(usize, usize)> {
+    let king = match color {
+        Color::White => Piece::WhiteKing,
+        Color::Black => Piece::BlackKing,
+    };
+
+    for y in 0..8 {
+        for x in 0..8 {
+            if board[y][x] == king {
+                return Some((x, y));
+            }
+        }
+    }
+
+    None
+}
+
+pub fn in_check(board: &[[Piece; 8]; 8], color: &Color) -> bool {
+    match king_position(board, color) {
+        Some((x, y)) => attack_set(board, &oposite_color(color))[y][x],
+        None => false,
+    }
+}
+
+// the square of whichever king is in check, if any
+pub fn checked_king(board: &[[Piece; 8]; 8]) -> Option<(usize, usize)> {
+    for color in [Color::White, Color::Black] {
+        if in_check(board, &color) {
+            return king_position(board, &color);
+        }
+    }
+
+    None
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MoveKind {
+    Quiet,
+    Capture,
+    Castle {
+        rook_from: (usize, usize),
+        rook_to: (usize, usize),
+    },
+    EnPassant {
+        captured: (usize, usize),
+    },
+    Promotion {
+        capture: bool,
+    },
+}
+
+impl MoveKind {
+    pub fn is_capture(&self) -> bool {
+        match self {
+            MoveKind::Capture | MoveKind::EnPassant { .. } => true,
+            MoveKind::Promotion { capture } => *capture,
+            MoveKind::Quiet | MoveKind::Castle { .. } => false,
+        }
+    }
+}
+
+// works out what kind of move this is from the board before the move was made
+pub fn classify_move(board: &[[Piece; 8]; 8], mv: &Move) -> MoveKind {
+    let piece = board[mv.start_y][mv.start_x];
+    let target = board[mv.end_y][mv.end_x];
+    let capture = target != Piece::None && piece_color(&target) != piece_color(&piece);
+
+    match piece {
+        Piece::WhiteKing | Piece::BlackKing if mv.start_y == mv.end_y && mv.start_x.abs_diff(mv.end_x) == 2 => {
+            if mv.end_x > mv.start_x {
+                MoveKind::Castle { rook_from: (7, mv.start_y), rook_to: (mv.end_x - 1, mv.start_y) }
+            }
+            else {
+                MoveKind::Castle { rook_from: (0, mv.start_y), rook_to: (mv.end_x + 1, mv.start_y) }
+            }
+        },
+        Piece::WhitePawn | Piece::BlackPawn => {
+            if mv.end_y == 0 || mv.end_y == 7 {
+                MoveKind::Promotion { capture }
+            }
+            else if mv.start_x != mv.end_x && target == Piece::None {
+                MoveKind::EnPassant { captured: (mv.end_x, mv.start_y) }
+            }
+            else if capture {
+                MoveKind::Capture
+            }
+            else {
+                MoveKind::Quiet
+            }
+        },
+        _ if capture => MoveKind::Capture,
+        _ => MoveKind::Quiet,
+    }
+}
+
+// every square that changed because of the move, the rook for castling and the taken pawn for en passant included
+pub fn affected_squares(board: &[[Piece; 8]; 8], mv: &Move) -> Vec<(usize, usize)> {
+    let mut squares = vec![(mv.start_x, mv.start_y), (mv.end_x, mv.end_y)];
+
+    match classify_move(board, mv) {
+        MoveKind::Castle { rook_from, rook_to } => {
+            squares.push(rook_from);
+            squares.push(rook_to);
+        },
+        MoveKind::EnPassant { captured } => squares.push(captured),
+        _ => {},
+    }
+
+    squares
+}
+
+// the board after the move. a promotion without a piece becomes a queen like in every backend
+pub fn apply_move(board: &[[Piece; 8]; 8], mv: &Move) -> [[Piece; 8]; 8] {
+    let mut after = *board;
+    let piece = board[mv.start_y][mv.start_x];
+
+    after[mv.start_y][mv.start_x] = Piece::None;
+    after[mv.end_y][mv.end_x] = piece;
+
+    match classify_move(board, mv) {
+        MoveKind::Castle { rook_from, rook_to } => {
+            after[rook_to.1][rook_to.0] = board[rook_from.1][rook_from.0];
+            after[rook_from.1][rook_from.0] = Piece::None;
+        },
+        MoveKind::EnPassant { captured } => after[captured.1][captured.0] = Piece::None,
+        MoveKind::Promotion { .. } => {
+            after[mv.end_y][mv.end_x] = match (mv.promotion, piece_color(&piece)) {
+                (Piece::None, Some(color)) => queen(&color),
+                (promotion, _) => promotion,
+            };
+        },
+        MoveKind::Quiet | MoveKind::Capture => {},
+    }
+
+    after
+}
+
+pub fn piece_value(piece: &Piece) -> i32 {
+    match piece {
+        Piece::WhitePawn | Piece::BlackPawn => 1,
+        Piece::WhiteKnight | Piece::BlackKnight => 3,
+        Piece::WhiteBishop | Piece::BlackBishop => 3,
+        Piece::WhiteRook | Piece::BlackRook => 5,
+        Piece::WhiteQueen | Piece::BlackQueen => 9,
+        Piece::WhiteKing | Piece::BlackKing | Piece::None => 0,
+    }
+}
+
+pub fn material(board: &[[Piece; 8]; 8], color: &Color) -> i32 {
+    board.iter()
+        .flatten()
+        .filter(|p| piece_color(p).as_ref() == Some(color))
+        .map(piece_value)
+        .sum()
+}
+
+// pieces that disappeared between two successive boards. only the side that lost a piece in total
+// counts, so a promotion (pawn becomes queen) isn't mistaken for a capture
+pub fn captured_between(before: &[[Piece; 8]; 8], after: &[[Piece; 8]; 8]) -> Vec<Piece> {
+    let mut captured = vec![];
+
+    for color in [Color::White, Color::Black] {
+        let count = |board: &[[Piece; 8]; 8], piece: &Piece| board.iter().flatten().filter(|p| *p == piece).count();
+        let total = |board: &[[Piece; 8]; 8]| board.iter().flatten().filter(|p| piece_color(p).as_ref() == Some(&color)).count();
+
+        if total(after) >= total(before) {
+            continue;
+        }
+
+        for piece in pieces_of(&color) {
+            for _ in count(after, &piece)..count(before, &piece) {
+                captured.push(piece);
+            }
+        }
+    }
+
+    captured
+}
+
+pub fn pieces_of(color: &Color) -> [Piece; 6] {
+    match color {
+        Color::White => [Piece::WhiteQueen, Piece::WhiteRook, Piece::WhiteBishop, Piece::WhiteKnight, Piece::WhitePawn, Piece::WhiteKing],
+        Color::Black => [Piece::BlackQueen, Piece::BlackRook, Piece::BlackBishop, Piece::BlackKnight, Piece::BlackPawn, Piece::BlackKing],
+    }
+}
+
+// moves that follow the piece movement rules but may leave the own king in check.
+// castling only checks that the king and rook are home and the squares between are empty
+pub fn pseudo_legal_moves(board: &[[Piece; 8]; 8], color: &Color) -> Vec<Move> {
+    let mut moves = vec![];
+
+    for y in 0..8 {
+        for x in 0..8 {
+            let piece = board[y][x];
+
+            if piece_color(&piece).as_ref() != Some(color) {
+                continue;
+            }
+
+            let targets = match piece {
+                Piece::WhitePawn | Piece::BlackPawn => pawn_targets(board, x, y, color),
+                _ => attacks_from(board, x, y).into_iter()
+                    .filter(|(tx, ty)| piece_color(&board[*ty][*tx]).as_ref() != Some(color))
+                    .collect(),
+            };
+
+            for (end_x, end_y) in targets {
+                let promotes = matches!(piece, Piece::WhitePawn | Piece::BlackPawn) && (end_y == 0 || end_y == 7);
+
+                if promotes {
+                    for promotion in &pieces_of(color)[0..4] {
+                        moves.push(Move { start_x: x, start_y: y, end_x, end_y, promotion: *promotion });
+                    }
+                }
+                else {
+                    moves.push(Move { start_x: x, start_y: y, end_x, end_y, promotion: Piece::None });
+                }
+            }
+        }
+    }
+
+    moves.append(&mut castling_moves(board, color));
+
+    moves
+}
+
+fn pawn_targets(board: &[[Piece; 8]; 8], x: usize, y: usize, color: &Color) -> Vec<(usize, usize)> {
+    let dy = pawn_direction(color);
+    let start_rank = match color {
+        Color::White => 1,
+        Color::Black => 6,
+    };
+    let mut targets = vec![];
+
+    let one = y as i32 + dy;
+    if on_board(x as i32, one) && board[one as usize][x] == Piece::None {
+        targets.push((x, one as usize));
+
+        let two = one + dy;
+        if y == start_rank && board[two as usize][x] == Piece::None {
+            targets.push((x, two as usize));
+        }
+    }
+
+    for (tx, ty) in attacks_from(board, x, y) {
+        if piece_color(&board[ty][tx]) == Some(oposite_color(color)) {
+            targets.push((tx, ty));
+        }
+    }
+
+    targets
+}
+
+fn castling_moves(board: &[[Piece; 8]; 8], color: &Color) -> Vec<Move> {
+    let (rank, king, rook) = match color {
+        Color::White => (0, Piece::WhiteKing, Piece::WhiteRook),
+        Color::Black => (7, Piece::BlackKing, Piece::BlackRook),
+    };
+    let mut moves = vec![];
+
+    if board[rank][4] != king {
+        return moves;
+    }
+
+    if board[rank][7] == rook && (5..7).all(|x| board[rank][x] == Piece::None) {
+        moves.push(Move { start_x: 4, start_y: rank, end_x: 6, end_y: rank, promotion: Piece::None });
+    }
+    if board[rank][0] == rook && (1..4).all(|x| board[rank][x] == Piece::None) {
+        moves.push(Move { start_x: 4, start_y: rank, end_x: 2, end_y: rank, promotion: Piece::None });
+    }
+
+    moves
+}
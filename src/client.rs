@@ -1,11 +1,12 @@
-use serde::{Serialize, Deserialize};
 use chess_network_protocol::*;
-use serde_json::de::IoRead;
 
 use std::net::TcpStream;
 use std::sync::mpsc::{Sender, Receiver};
 
-use crate::{TcpToGame, GameToTcp};
+use crate::{TcpToGame, GameToTcp, board_utils, extension};
+use crate::connection::{Connection, Received};
+use crate::extension::Extension;
+use crate::transcript::Side;
 
 fn switch_turn(turn: &Color) -> Color {
     match turn {
@@ -15,101 +16,207 @@ fn switch_turn(turn: &Color) -> Color {
 }
 
 pub fn run(sender: Sender<TcpToGame>, receiver: Receiver<GameToTcp>, server_color: Color, ip: String) {
-    let stream = TcpStream::connect(ip + ":8384").unwrap();
-    let mut de = serde_json::Deserializer::from_reader(&stream);
+    connect(sender, receiver, server_color, ip + ":8384");
+}
+
+// the session with a server at a full address, the mock peer uses this on loopback
+pub fn connect(sender: Sender<TcpToGame>, receiver: Receiver<GameToTcp>, mut server_color: Color, address: String) {
+    let stream = match TcpStream::connect(&address) {
+        Ok(stream) => stream,
+        Err(e) => {
+            let _ = sender.send(TcpToGame::Error { message: format!("Could not connect to {}: {}", address, e) });
+            return;
+        },
+    };
+    let mut connection = Connection::new(stream, sender, receiver, Side::Client);
 
     let handshake = ClientToServerHandshake {
         server_color: server_color.clone(),
     };
 
     //send
-    serde_json::to_writer(&stream, &handshake).unwrap();
+    connection.write(&handshake).unwrap();
 
-    //receive
-    let deserialized = ServerToClientHandshake::deserialize(&mut de).unwrap();
+    // after a rematch the server sends a new handshake with the colors swapped
+    loop {
+        //receive
+        let deserialized: ServerToClientHandshake = match connection.receive_peer() {
+            Ok(Received::Peer(Ok(handshake))) => handshake,
+            Ok(Received::Peer(Err(e))) => {
+                connection.close(format!("The server's handshake isn't part of the protocol: {}", e));
+                return;
+            },
+            // the server or the gui left, nothing else can come before the handshake
+            _ => return,
+        };
 
-    let mut turn = Color::White;
+        connection.offered = extension::supported();
+        connection.features = extension::intersection(&connection.offered, &deserialized.features);
 
-    sender.send(TcpToGame::Handshake {
-        board: deserialized.board,
-        moves: deserialized.moves,
-        features: deserialized.features,
-        server_color: server_color.clone(),
-    }).unwrap();
+        // tell the server what we have in common, only a server with one of our extensions can read it
+        if extension::features().iter().any(|ours| extension::has(&deserialized.features, ours)) {
+            connection.write(&Extension::Features(connection.features.clone())).unwrap();
+        }
+
+        let turn = turn_from_moves(&deserialized.board, &deserialized.moves).unwrap_or(Color::White);
 
-    if crate::your_turn(&turn, &server_color, false) {
-        turn = make_move(sender.clone(), &receiver, turn, &stream);
+        connection.sender.send(TcpToGame::Handshake {
+            board: deserialized.board,
+            moves: deserialized.moves,
+            waiting_moves: board_utils::pseudo_legal_moves(&deserialized.board, &switch_turn(&turn)),
+            features: connection.features.clone(),
+            server_color: server_color.clone(),
+        }).unwrap();
+
+        if !play(&mut connection, &server_color, deserialized.board, turn) {
+            return;
+        }
+
+        // the gui only offers a rematch if the server advertised it
+        if !connection.wait_for_rematch(|_, _| {}) {
+            return;
+        }
+
+        server_color = switch_turn(&server_color);
     }
+}
+
+// plays until the game is over. whose turn it is comes from the boards the server sends, not from
+// counting messages, so a lost or unexpected message can't leave the client on the wrong turn.
+// returns false if the server or the gui left before the game was over
+fn play(connection: &mut Connection, server_color: &Color, mut local_board: [[Piece; 8]; 8], mut turn: Color) -> bool {
+    let mut moved = false;
 
     loop {
-        let deserialized = ServerToClient::deserialize(&mut de).unwrap();
+        // the server can still send something before we move, a takeback that gives it the turn back
+        let received = if crate::your_turn(&turn, server_color, false) && !moved {
+            connection.receive_any::<ServerToClient>()
+        }
+        else {
+            connection.receive_peer::<ServerToClient>()
+        };
+
+        let deserialized: ServerToClient = match received {
+            Ok(Received::Gui(message)) => {
+                moved = send(connection, message);
+                continue;
+            },
+            Ok(Received::Peer(Ok(message))) => message,
+            // the server keeps going after a message we can't read, so the client does too
+            Ok(Received::Peer(Err(e))) => {
+                connection.sender.send(TcpToGame::Error {
+                    message: format!("The server sent a message that isn't part of the protocol: {}", e),
+                }).unwrap();
+                continue;
+            },
+            // only the server's connection agrees to takebacks and draws
+            Ok(Received::Takeback(_)) | Ok(Received::Draw) => continue,
+            Ok(Received::Closed) | Err(_) => return false,
+        };
+
+        // whatever the server sent answers our move
+        moved = false;
 
         match deserialized {
             ServerToClient::State { board, moves, joever, move_made } => {
-                turn = switch_turn(&turn);
+                let mover = crate::piece_color(&board[move_made.end_y][move_made.end_x]);
 
-                sender.send(TcpToGame::State { 
+                if let Some(message) = desync(&local_board, &board, &move_made, &turn, mover) {
+                    connection.sender.send(TcpToGame::Desync { message }).unwrap();
+                }
+
+                // the piece that moved is on the end square now, the other side moves next
+                let ours = mover.as_ref() == Some(&switch_turn(server_color));
+                turn = switch_turn(&mover.unwrap_or(turn));
+                local_board = board;
+                connection.moved(ours);
+
+                connection.sender.send(TcpToGame::State { 
                     board, 
                     moves, 
-                    joever, 
+                    joever: joever.clone(), 
                     move_made, 
                     turn: turn.clone(),
+                    waiting_moves: board_utils::pseudo_legal_moves(&board, &switch_turn(&turn)),
                 }).unwrap();
+
+                if joever != Joever::Ongoing {
+                    return true;
+                }
+            },
+            // the server sends its board and moves with every error, so whatever went wrong the
+            // client can start over from there
+            ServerToClient::Error { board, moves, joever, message } => {
+                turn = turn_from_moves(&board, &moves).unwrap_or(turn);
+                local_board = board;
+
+                connection.sender.send(TcpToGame::Resync {
+                    board,
+                    moves,
+                    joever: joever.clone(),
+                    turn: turn.clone(),
+                    waiting_moves: board_utils::pseudo_legal_moves(&board, &switch_turn(&turn)),
+                    message,
+                }).unwrap();
+
+                if joever != Joever::Ongoing {
+                    return true;
+                }
+            },
+            // both end the game, the server doesn't take moves after them
+            ServerToClient::Draw { board, moves } => {
+                connection.sender.send(TcpToGame::Draw { board, moves }).unwrap();
+                return true;
+            },
+            ServerToClient::Resigned { board, joever } => {
+                connection.sender.send(TcpToGame::Resigned { board, joever }).unwrap();
+                return true;
             },
-            ServerToClient::Error { .. } => { unreachable!() },
-            ServerToClient::Draw { board, moves } => {panic!("Draw not implemented")},
-            ServerToClient::Resigned { board, joever } => {panic!("Resigned not implemented")},
         }
+    }
+}
 
-        turn = make_move(sender.clone(), &receiver, turn, &stream);
+// compares the server's board with the move applied to the board the client had, and the color
+// of the piece that moved with whose turn the client thought it was
+fn desync(local: &[[Piece; 8]; 8], remote: &[[Piece; 8]; 8], move_made: &Move, turn: &Color, mover: Option<Color>) -> Option<String> {
+    let square = |x: usize, y: usize| crate::cords_to_square(x as f32, y as f32);
+    let mv = square(move_made.start_x, move_made.start_y) + &square(move_made.end_x, move_made.end_y);
+
+    if mover.as_ref() != Some(turn) {
+        return Some(format!("The server played {} for the side that wasn't to move, continuing from the server's board", mv));
+    }
+
+    if board_utils::apply_move(local, move_made) != *remote {
+        return Some(format!("The server's board doesn't match {} played on ours, continuing from the server's board", mv));
     }
+
+    None
+}
+
+// the moves only ever belong to the side to move
+fn turn_from_moves(board: &[[Piece; 8]; 8], moves: &[Move]) -> Option<Color> {
+    moves.first().and_then(|m| crate::piece_color(&board[m.start_y][m.start_x]))
 }
 
-fn make_move(sender: Sender<TcpToGame>, receiver: &Receiver<GameToTcp>, turn: Color, stream: &TcpStream) -> Color {
-    let mut de = serde_json::Deserializer::from_reader(stream);
-    
-    match receiver.recv().unwrap() {
+// sends what the gui asked for, returns true if the server answers it. the answer is read by play
+fn send(connection: &mut Connection, message: GameToTcp) -> bool {
+    match message {
         GameToTcp::Move(move_made) => {
             let mv = ClientToServer::Move(move_made);
 
             //send
-            serde_json::to_writer(stream, &mv).unwrap();
-            let deserialized = ServerToClient::deserialize(&mut de).unwrap();
-        
-            match deserialized {
-                ServerToClient::State { board, moves, joever, move_made } => {
-                    sender.send(TcpToGame::State { 
-                        board, 
-                        moves, 
-                        joever, 
-                        move_made, 
-                        turn: switch_turn(&turn),
-                    }).unwrap();
-                    
-                    return switch_turn(&turn);
-                },
-                ServerToClient::Error { message, .. } => {
-                    sender.send(TcpToGame::Error { message }).unwrap();
-        
-                    return make_move(sender, receiver, turn, stream);
-                },
-                ServerToClient::Draw { board, moves } => {panic!("Draw not implemented")},
-                ServerToClient::Resigned { board, joever } => {panic!("Resigned not implemented")},
-            }
-        },
-        GameToTcp::Draw => {
-            /* let state = ClientToServer::Draw;
-
-            //send
-            serde_json::to_writer(stream, &state).unwrap(); */
-            todo!()
+            connection.write(&mv).unwrap();
+            true
         },
         GameToTcp::Resign => {
-            /* let state = ClientToServer::Resign;
+            let state = ClientToServer::Resign;
 
             //send
-            serde_json::to_writer(stream, &state).unwrap(); */
-            todo!()
+            connection.write(&state).unwrap();
+            true
         },
+        // a rematch is only asked for after the game, chat, takebacks and draw offers are handled by
+        // the connection
+        GameToTcp::Rematch | GameToTcp::Chat(_) | GameToTcp::Takeback | GameToTcp::Draw | GameToTcp::DeclineDraw => false,
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,366 @@
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use std::collections::VecDeque;
+use std::io;
+use std::net::{TcpStream, Shutdown};
+use std::sync::mpsc::{Sender, Receiver, RecvError, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+use chess_network_protocol::{Features, ClientToServer};
+
+use crate::{TcpToGame, GameToTcp, extension};
+use crate::extension::Extension;
+use crate::transcript::{Transcript, Side};
+
+pub enum Received<T> {
+    Gui(GameToTcp),
+    Peer(serde_json::Result<T>),
+    // both sides agreed to a takeback, true if this side asked for it. only the server gets this
+    Takeback(bool),
+    // the server accepted the client's draw offer. a client accepting the server's offer sends
+    // ClientToServer::Draw instead
+    Draw,
+    // the other side left or sent something unreadable, the gui has been told and the thread should stop
+    Closed,
+}
+
+pub const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// the tcp stream and the gui channels of one network thread. extension messages (chat and so on)
+// can arrive at any time, so both sides are polled and the extensions are handled here, leaving
+// the server and client loops with only the chess-network-protocol messages they expect
+pub struct Connection {
+    stream: TcpStream,
+    pub sender: Sender<TcpToGame>,
+    receiver: Receiver<GameToTcp>,
+    // protocol messages from the other side that came while waiting for the gui
+    unread: VecDeque<Value>,
+    // gui messages that came while waiting for the other side
+    unsent: VecDeque<GameToTcp>,
+    rematch_offered: bool,
+    side: Side,
+    // Some(true) while this side's takeback request is open, Some(false) while the other side's is
+    takeback_asked: Option<bool>,
+    takeback_agreed: Option<bool>,
+    // Some(true) while this side's draw offer is open, Some(false) while the other side's is
+    draw_offered: Option<bool>,
+    draw_agreed: bool,
+    // what this side offered in the handshake, and what both sides ended up with
+    pub offered: Vec<Features>,
+    pub features: Vec<Features>,
+    transcript: Option<Transcript>,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream, sender: Sender<TcpToGame>, receiver: Receiver<GameToTcp>, side: Side) -> Self {
+        let transcript = match Transcript::create(side) {
+            Ok(transcript) => transcript,
+            Err(e) => {
+                eprintln!("could not create the transcript: {}", e);
+                None
+            },
+        };
+
+        Connection {
+            stream,
+            sender,
+            receiver,
+            unread: VecDeque::new(),
+            unsent: VecDeque::new(),
+            rematch_offered: false,
+            side,
+            takeback_asked: None,
+            takeback_agreed: None,
+            draw_offered: None,
+            draw_agreed: false,
+            offered: vec![],
+            features: vec![],
+            transcript,
+        }
+    }
+
+    pub fn write<T: Serialize>(&self, message: &T) -> serde_json::Result<()> {
+        let value = serde_json::to_value(message)?;
+
+        if let Some(transcript) = &self.transcript {
+            transcript.sent(&value);
+        }
+
+        serde_json::to_writer(&self.stream, &value)
+    }
+
+    // whichever comes first, a message from the gui or a protocol message from the other side
+    pub fn receive_any<T: DeserializeOwned>(&mut self) -> Result<Received<T>, RecvError> {
+        self.next(true)
+    }
+
+    // like receive_any but the gui's moves wait for the next receive_any, it's the other side's turn
+    pub fn receive_peer<T: DeserializeOwned>(&mut self) -> Result<Received<T>, RecvError> {
+        self.next(false)
+    }
+
+    fn next<T: DeserializeOwned>(&mut self, gui: bool) -> Result<Received<T>, RecvError> {
+        loop {
+            if let Some(ours) = self.takeback_agreed.take() {
+                return Ok(Received::Takeback(ours));
+            }
+            if self.draw_agreed {
+                self.draw_agreed = false;
+                return Ok(Received::Draw);
+            }
+            if gui {
+                if let Some(message) = self.unsent.pop_front() {
+                    return Ok(Received::Gui(message));
+                }
+            }
+            if let Some(value) = self.unread.pop_front() {
+                return Ok(Received::Peer(serde_json::from_value(value)));
+            }
+
+            match self.receiver.try_recv() {
+                Ok(message) => match self.from_gui(message) {
+                    Some(message) if gui => return Ok(Received::Gui(message)),
+                    Some(message) => self.unsent.push_back(message),
+                    None => {},
+                },
+                Err(TryRecvError::Empty) => {},
+                Err(TryRecvError::Disconnected) => return Err(RecvError),
+            }
+
+            if let Err(e) = self.poll_peer() {
+                self.disconnected(&e);
+                return Ok(Received::Closed);
+            }
+        }
+    }
+
+    // ends the session, the gui shows the message and the thread returns after this
+    pub fn close(&self, message: String) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+        let _ = self.sender.send(TcpToGame::Disconnected { message });
+    }
+
+    fn disconnected(&self, e: &io::Error) {
+        let message = match e.kind() {
+            io::ErrorKind::UnexpectedEof => "Your opponent left".to_string(),
+            io::ErrorKind::InvalidData => e.to_string(),
+            _ => format!("Lost the connection to the opponent: {}", e),
+        };
+
+        self.close(message);
+    }
+
+    // after a game both sides can ask for a rematch, returns true once both have.
+    // returns false when the gui went back to the menu or the opponent left, the connection is closed then
+    // protocol messages that come in meanwhile are given to on_message
+    pub fn wait_for_rematch(&mut self, mut on_message: impl FnMut(&mut Connection, Value)) -> bool {
+        let mut asked = false;
+
+        // moves and draw offers that were on their way when the game ended
+        self.unsent.clear();
+
+        while !(asked && self.rematch_offered) {
+            match self.receiver.try_recv() {
+                Ok(GameToTcp::Rematch) if !asked => {
+                    if self.write(&Extension::Rematch).is_err() {
+                        return false;
+                    }
+                    asked = true;
+                },
+                Ok(GameToTcp::Chat(text)) => {
+                    if self.write(&Extension::Chat(text)).is_err() {
+                        return false;
+                    }
+                },
+                Ok(_) => {},
+                Err(TryRecvError::Empty) => {},
+                Err(TryRecvError::Disconnected) => {
+                    let _ = self.stream.shutdown(Shutdown::Both);
+                    return false;
+                },
+            }
+
+            if let Err(e) = self.poll_peer() {
+                self.disconnected(&e);
+                return false;
+            }
+            while let Some(value) = self.unread.pop_front() {
+                on_message(self, value);
+            }
+        }
+
+        self.rematch_offered = false;
+        self.takeback_asked = None;
+        self.takeback_agreed = None;
+        self.draw_offered = None;
+        self.draw_agreed = false;
+        true
+    }
+
+    // a move was played, so an open takeback request was about another position. a draw offer
+    // stands until the side it was made to moves
+    pub fn moved(&mut self, ours: bool) {
+        self.takeback_asked = None;
+
+        if self.draw_offered == Some(!ours) {
+            self.draw_offered = None;
+        }
+    }
+
+    // the other side offered a draw, or accepted ours. returns true if both sides agree now
+    pub fn draw_offer_received(&mut self) -> bool {
+        if self.draw_offered == Some(true) {
+            self.draw_offered = None;
+            return true;
+        }
+
+        self.draw_offered = Some(false);
+        let _ = self.sender.send(TcpToGame::DrawOffered);
+        false
+    }
+
+    // chat, takeback requests and draw offers are handled here, anything else is for the caller
+    fn from_gui(&mut self, message: GameToTcp) -> Option<GameToTcp> {
+        match message {
+            GameToTcp::Chat(text) => {
+                let _ = self.write(&Extension::Chat(text));
+            },
+            GameToTcp::Takeback => {
+                match self.takeback_asked {
+                    Some(true) => {},
+                    // accepting the other side's request
+                    Some(false) => {
+                        let _ = self.write(&Extension::Takeback);
+                        self.agree(false);
+                    },
+                    None => {
+                        let _ = self.write(&Extension::Takeback);
+                        self.takeback_asked = Some(true);
+                    },
+                }
+            },
+            GameToTcp::Draw => {
+                match (self.draw_offered, self.side) {
+                    (Some(true), _) => {},
+                    // accepting the other side's offer
+                    (Some(false), Side::Server) => {
+                        self.draw_offered = None;
+                        self.draw_agreed = true;
+                    },
+                    (Some(false), Side::Client) => {
+                        let _ = self.write(&ClientToServer::Draw);
+                        self.draw_offered = None;
+                    },
+                    (None, Side::Server) => {
+                        let _ = self.write(&Extension::DrawOffer);
+                        self.draw_offered = Some(true);
+                    },
+                    (None, Side::Client) => {
+                        let _ = self.write(&ClientToServer::Draw);
+                        self.draw_offered = Some(true);
+                    },
+                }
+            },
+            GameToTcp::DeclineDraw => {
+                if self.draw_offered == Some(false) {
+                    self.draw_offered = None;
+
+                    // a client without draw offers can't read the answer, its offer just lapses
+                    if extension::supports(&self.features, extension::DRAW_OFFERS) {
+                        let _ = self.write(&Extension::DrawDeclined);
+                    }
+                }
+            },
+            message => return Some(message),
+        }
+
+        None
+    }
+
+    // the server takes the moves back, the client only hears about it
+    fn agree(&mut self, ours: bool) {
+        self.takeback_asked = None;
+
+        if self.side == Side::Server {
+            self.takeback_agreed = Some(ours);
+        }
+    }
+
+    // reads one message from the other side if there is one. extensions go to the gui, the rest is
+    // kept for receive
+    fn poll_peer(&mut self) -> io::Result<()> {
+        if !readable(&self.stream)? {
+            thread::sleep(POLL_INTERVAL);
+            return Ok(());
+        }
+
+        // a whole value is read first so a message that isn't an extension isn't half consumed
+        let mut de = serde_json::Deserializer::from_reader(&self.stream);
+        let value = match Value::deserialize(&mut de) {
+            Ok(value) => value,
+            Err(e) if e.is_io() || e.is_eof() => return Err(e.into()),
+            // there's no telling where the next message starts after broken json, so the rest of the
+            // stream can't be read
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("The opponent sent something that isn't JSON: {}", e))),
+        };
+
+        if let Some(transcript) = &self.transcript {
+            transcript.received(&value);
+        }
+
+        match serde_json::from_value(value.clone()) {
+            Ok(Extension::Features(features)) => {
+                self.features = extension::intersection(&self.offered, &features);
+                let _ = self.sender.send(TcpToGame::Features(self.features.clone()));
+            },
+            Ok(Extension::Rematch) => {
+                self.rematch_offered = true;
+                let _ = self.sender.send(TcpToGame::RematchOffered);
+            },
+            Ok(Extension::Chat(text)) => {
+                let _ = self.sender.send(TcpToGame::Chat(text));
+            },
+            Ok(Extension::Takeback) => match self.takeback_asked {
+                Some(true) => self.agree(true),
+                _ => {
+                    self.takeback_asked = Some(false);
+                    let _ = self.sender.send(TcpToGame::TakebackOffered);
+                },
+            },
+            Ok(Extension::TakenBack(plies)) => {
+                self.takeback_asked = None;
+                let _ = self.sender.send(TcpToGame::TakenBack(plies));
+            },
+            // when both offered at once the server takes the client's offer as the answer to its own
+            Ok(Extension::DrawOffer) => {
+                self.draw_offer_received();
+            },
+            Ok(Extension::DrawDeclined) => {
+                if self.draw_offered == Some(true) {
+                    self.draw_offered = None;
+                    let _ = self.sender.send(TcpToGame::DrawDeclined);
+                }
+            },
+            Err(_) => self.unread.push_back(value),
+        }
+
+        Ok(())
+    }
+}
+
+// true if there is something to read without blocking, Err if the other side closed the connection
+fn readable(stream: &TcpStream) -> io::Result<bool> {
+    stream.set_nonblocking(true)?;
+    let result = stream.peek(&mut [0; 1]);
+    stream.set_nonblocking(false)?;
+
+    match result {
+        Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+        Err(e) => Err(e),
+    }
+}
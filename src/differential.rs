@@ -0,0 +1,166 @@
+use chess_network_protocol::*;
+
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::server::UniversalGame;
+use crate::{notation, extension, backends};
+
+// cargo run -- differential [games] [plies] [seed] [backends...]
+// plays random games on every backend at once and stops at the first ply where they disagree about
+// the board, the turn, the result or the possible moves. the moves are picked from the first
+// backend that generates them. exits with 1 on a divergence
+
+const GAMES: u64 = 100;
+const PLIES: u64 = 200;
+
+pub fn run(args: &[String]) {
+    let number = |i: usize, default: u64| args.get(i).and_then(|a| a.parse().ok()).unwrap_or(default);
+    let games = number(0, GAMES);
+    let plies = number(1, PLIES) as usize;
+    let seed = number(2, SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(1));
+
+    let names: Vec<&str> = match args.get(3..) {
+        Some(names) if !names.is_empty() => names.iter().map(|n| n.as_str()).collect(),
+        _ => backends::names(),
+    };
+    if let Some(unknown) = names.iter().find(|n| backends::find(n).is_none()) {
+        eprintln!("unknown backend {}, expected one of {}", unknown, backends::names().join(", "));
+        process::exit(1);
+    }
+
+    // printed so a divergence can be found again
+    println!("seed {}", seed);
+    let mut rng = Rng(seed.max(1));
+
+    for game in 1..=games {
+        if let Err(divergence) = play(&names, &mut rng, plies) {
+            println!("game {}: {}", game, divergence);
+            process::exit(1);
+        }
+    }
+
+    println!("{} games, {} agree", games, names.join(", "));
+}
+
+// one game, Err describes the first divergence
+fn play(names: &[&str], rng: &mut Rng, plies: usize) -> Result<(), String> {
+    let mut games: Vec<(&str, Box<dyn UniversalGame>)> = names.iter()
+        .filter_map(|n| backends::find(n))
+        .map(|b| (b.name, (b.new)()))
+        .collect();
+    let start = games[0].1.board();
+    let mut moves: Vec<Move> = vec![];
+
+    loop {
+        let snapshots: Vec<(&str, Snapshot)> = games.iter_mut().map(|(name, game)| (*name, snapshot(game.as_mut()))).collect();
+
+        if let Some(difference) = compare(&snapshots) {
+            return Err(report(&start, &moves, &difference));
+        }
+
+        let (_, first) = &snapshots[0];
+        let candidates = match snapshots.iter().find_map(|(_, s)| s.moves.clone()) {
+            Some(candidates) => candidates,
+            None => return Err("none of the backends generates moves".to_string()),
+        };
+
+        if first.joever != Joever::Ongoing || candidates.is_empty() || moves.len() >= plies {
+            return Ok(());
+        }
+
+        let mv = candidates[rng.below(candidates.len())];
+
+        for (name, game) in &mut games {
+            if let Err(e) = game.try_move(mv) {
+                let difference = format!("{} refuses {}: {}", name, notation::to_coordinate(&mv), e);
+                return Err(report(&start, &moves, &difference));
+            }
+        }
+
+        moves.push(mv);
+    }
+}
+
+fn report(start: &[[Piece; 8]; 8], moves: &[Move], difference: &str) -> String {
+    let moves_text: Vec<String> = moves.iter().map(notation::to_coordinate).collect();
+
+    format!("{} after {} plies\n  fen: {}\n  moves: {}",
+        difference,
+        moves.len(),
+        notation::to_fen(start, moves),
+        moves_text.join(" "),
+    )
+}
+
+struct Snapshot {
+    board: [[Piece; 8]; 8],
+    turn: Color,
+    joever: Joever,
+    // None for the backends that don't generate moves
+    moves: Option<Vec<Move>>,
+}
+
+fn snapshot(game: &mut dyn UniversalGame) -> Snapshot {
+    let generates = extension::has(&game.features(), &Features::PossibleMoveGeneration);
+
+    Snapshot {
+        board: game.board(),
+        turn: game.turn(),
+        joever: game.joever(),
+        moves: if generates { Some(game.possible_moves()) } else { None },
+    }
+}
+
+// every backend against the first one
+fn compare(snapshots: &[(&str, Snapshot)]) -> Option<String> {
+    let (first_name, first) = &snapshots[0];
+
+    for (name, other) in &snapshots[1..] {
+        if other.board != first.board {
+            return Some(format!("{} and {} disagree about the board, {} against {}",
+                first_name, name, notation::placement(&first.board), notation::placement(&other.board)));
+        }
+        if other.turn != first.turn {
+            return Some(format!("{} says it is {:?}'s turn, {} says {:?}'s", first_name, first.turn, name, other.turn));
+        }
+        if other.joever != first.joever {
+            return Some(format!("{} says the game is {:?}, {} says {:?}", first_name, first.joever, name, other.joever));
+        }
+
+        if let (Some(first_moves), Some(other_moves)) = (&first.moves, &other.moves) {
+            let only_first = missing(first_moves, other_moves);
+            let only_other = missing(other_moves, first_moves);
+
+            if !only_first.is_empty() || !only_other.is_empty() {
+                return Some(format!("{} and {} disagree about the possible moves, only {} has [{}], only {} has [{}]",
+                    first_name, name, first_name, only_first.join(" "), name, only_other.join(" ")));
+            }
+        }
+    }
+
+    None
+}
+
+// the moves in a that aren't in b
+fn missing(a: &[Move], b: &[Move]) -> Vec<String> {
+    a.iter()
+        .filter(|m| !b.contains(m))
+        .map(notation::to_coordinate)
+        .collect()
+}
+
+// xorshift, good enough to pick moves and needs no dependency
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
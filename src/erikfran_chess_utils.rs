@@ -9,6 +9,7 @@ pub struct Game {
     turn: Color,
     joever: Joever,
     features: Vec<Features>,
+    history: Vec<Move>,
     game: chess::Game,
 }
 
@@ -21,6 +22,7 @@ impl UniversalGame for Game {
             turn: Color::White,
             joever: Joever::Ongoing,
             features: vec![Features::Castling, Features::PossibleMoveGeneration],
+            history: vec![],
             game,
         }
     }
@@ -57,6 +59,7 @@ impl UniversalGame for Game {
         self.board = self.game.board.into_network();
         self.turn = self.game.turn.into_network();
         self.joever = self.game.game_status.into_network();
+        self.history.push(mv);
 
         Ok(())
     }
@@ -99,6 +102,14 @@ impl UniversalGame for Game {
     fn features(&self) -> Vec<Features> {
         self.features.clone()
     }
+
+    fn history(&self) -> &[Move] {
+        &self.history
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
 }
 
 pub trait IntoNetwork<T> {
@@ -0,0 +1,85 @@
+use serde::{Serialize, Deserialize};
+use chess_network_protocol::*;
+
+// messages on top of chess-network-protocol. the server advertises the ones it understands with
+// Features::Other in the handshake, the client answers with the ones it understands too and
+// neither side sends an extension the other didn't announce
+pub const REMATCH: &str = "rematch";
+pub const CHAT: &str = "chat";
+pub const TAKEBACK: &str = "takeback";
+pub const DRAW_OFFERS: &str = "draw";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Extension {
+    Features(Vec<Features>),
+    Rematch,
+    Chat(String),
+    // asking for a takeback, or agreeing to the other side's request
+    Takeback,
+    // from the server, the plies it took back. the board and moves follow as a ServerToClient::Error
+    TakenBack(usize),
+    // the server offering a draw, the client offers and accepts with ClientToServer::Draw
+    DrawOffer,
+    // turning down the other side's draw offer
+    DrawDeclined,
+}
+
+pub fn features() -> Vec<Features> {
+    vec![
+        Features::Other(REMATCH.to_string()),
+        Features::Other(CHAT.to_string()),
+        Features::Other(TAKEBACK.to_string()),
+        Features::Other(DRAW_OFFERS.to_string()),
+    ]
+}
+
+// everything the gui can deal with when the rules come from the other side's backend
+pub fn supported() -> Vec<Features> {
+    let mut supported = vec![
+        Features::Castling,
+        Features::EnPassant,
+        Features::Promotion,
+        Features::PossibleMoveGeneration,
+        Features::Stalemate,
+    ];
+    supported.append(&mut features());
+    supported
+}
+
+pub fn supports(features: &[Features], name: &str) -> bool {
+    features.iter().any(|f| matches!(f, Features::Other(other) if other == name))
+}
+
+pub fn has(features: &[Features], feature: &Features) -> bool {
+    features.iter().any(|f| same(f, feature))
+}
+
+fn same(a: &Features, b: &Features) -> bool {
+    match (a, b) {
+        (Features::Other(a), Features::Other(b)) => a == b,
+        _ => std::mem::discriminant(a) == std::mem::discriminant(b),
+    }
+}
+
+pub fn name(feature: &Features) -> &str {
+    match feature {
+        Features::Castling => "Castling",
+        Features::EnPassant => "En Passant",
+        Features::Promotion => "Promotion",
+        Features::PossibleMoveGeneration => "Possible Move Generation",
+        Features::Stalemate => "Stalemate",
+        Features::Other(f) => f.as_str(),
+    }
+}
+
+// the features both sides have, whatever only one side has is logged
+pub fn intersection(ours: &[Features], theirs: &[Features]) -> Vec<Features> {
+    for feature in ours.iter().filter(|f| !has(theirs, f)) {
+        eprintln!("feature mismatch: the other side doesn't support {}", name(feature));
+    }
+    for feature in theirs.iter().filter(|f| !has(ours, f)) {
+        eprintln!("feature mismatch: the other side supports {} but we don't", name(feature));
+    }
+
+    ours.iter().filter(|f| has(theirs, f)).cloned().collect()
+}
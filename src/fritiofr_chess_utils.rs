@@ -10,6 +10,7 @@ pub struct Game {
     turn: Color,
     joever: Joever,
     features: Vec<Features>,
+    history: Vec<Move>,
     game: chess::Game,
 }
 
@@ -22,6 +23,7 @@ impl UniversalGame for Game {
             turn: Color::White,
             joever: Joever::Ongoing,
             features: vec![Features::Castling, Features::EnPassant, Features::Promotion, Features::PossibleMoveGeneration],
+            history: vec![],
             game,
         }
     }
@@ -53,6 +55,7 @@ impl UniversalGame for Game {
         self.board = self.game.get_board().into_network();
         self.turn = self.game.get_turn().into_network();
         self.joever = self.game.is_checkmate().into_network(&self.turn);
+        self.history.push(mv);
 
         Ok(())
     }
@@ -98,6 +101,14 @@ impl UniversalGame for Game {
     fn features(&self) -> Vec<Features> {
         self.features.clone()
     }
+
+    fn history(&self) -> &[Move] {
+        &self.history
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
 }
 
 pub trait IntoNetwork<T> {
@@ -1,6 +1,6 @@
-#![feature(let_chains)]
 use ggegui::egui::{TextBuffer, Mesh};
 use ggez::event::MouseButton;
+use ggez::input::keyboard::{KeyCode, KeyInput, KeyMods};
 use ggez::{event, conf};
 use ggez::graphics::{self, Rect, Text, PxScale, DrawParam, TextFragment};
 use ggez::{Context, GameResult, glam};
@@ -12,9 +12,26 @@ use server::UniversalGame;
 mod redkar_chess_utils;
 mod fritiofr_chess_utils;
 mod erikfran_chess_utils;
+mod notation;
+mod board_utils;
+mod annotations;
+mod pgn;
+mod extension;
+mod connection;
+mod transcript;
+mod replay;
+#[cfg(test)]
+mod mock_peer;
+#[cfg(test)]
+mod test_utils;
+mod differential;
+mod perft;
+mod rules;
+mod backends;
 
 use std::f32::consts::PI;
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::time::Instant;
 use std::{env, path, thread, cmp::Ord};
 
 use ggegui::{egui, Gui};
@@ -34,11 +51,22 @@ const CORD_OFFSET: f32 = 35.0;
 const CORD_FONT_SIZE: f32 = 30.0;
 const MOVE_RADIUS: f32 = 25.0 * SCALE;
 const MOVE_CAPTURE_SIZE: f32 = 25.0 * SCALE;
+const TRAY_SIZE: f32 = 35.0 * SCALE;
+const TRAY_SPACING: f32 = 0.6;
+const ARROW_WIDTH: f32 = 15.0 * SCALE;
+const ARROW_HEAD: f32 = 45.0 * SCALE;
+const CONTROLS_TEXT: &str = "Controls:\n\nHold left click and drag to move a piece and just release left click on the destination square to make the move. Moves made while waiting are queued as premoves.\n\nRight click a square to mark it and right drag to draw an arrow, hold shift, ctrl or alt for other colors.\n\nYou can also type a move like e4, Nf3, O-O or e7e8q in the move box and press enter.\n\nSpace or escape skips the move animation.";
+
+// seconds to wait for the answer to a move before offering to leave the game
+const MOVE_TIMEOUT: f32 = 10.0;
+// seconds a move takes to slide, the setup screen can change it. tests don't wait for pieces to slide
+const ANIMATION_DURATION: f32 = if cfg!(test) { 0.0 } else { 0.25 };
 
 pub enum TcpToGame {
     Handshake {
         board: [[Piece; 8]; 8],
         moves: Vec<Move>,
+        waiting_moves: Vec<Move>,
         features: Vec<Features>,
         server_color: Color,
     },
@@ -48,10 +76,24 @@ pub enum TcpToGame {
         joever: Joever,
         move_made: Move,
         turn: Color,
+        waiting_moves: Vec<Move>,
     },
     Error {
         message: String,
     },
+    // the client's board and the server's disagree, the server's is used
+    Desync {
+        message: String,
+    },
+    // an error from the server together with its state, the client starts over from that
+    Resync {
+        board: [[Piece; 8]; 8],
+        moves: Vec<Move>,
+        joever: Joever,
+        turn: Color,
+        waiting_moves: Vec<Move>,
+        message: String,
+    },
     Draw {
         board: [[Piece; 8]; 8],
         moves: Vec<Move>,
@@ -60,19 +102,99 @@ pub enum TcpToGame {
         board: [[Piece; 8]; 8],
         joever: Joever,
     },
+    RematchOffered,
+    TakebackOffered,
+    DrawOffered,
+    // the opponent turned down this side's draw offer
+    DrawDeclined,
+    // the plies the server took back, the board and moves follow as a Resync
+    TakenBack(usize),
+    // the features both sides support, sent to the server's gui once the client answered the handshake
+    Features(Vec<Features>),
+    Chat(String),
+    // the opponent left or sent something unreadable, the network thread stops after this
+    Disconnected {
+        message: String,
+    },
 }
 
 pub enum GameToTcp {
     Move(Move),
+    // offers a draw, or accepts the opponent's offer
     Draw,
+    DeclineDraw,
     Resign,
+    Rematch,
+    Chat(String),
+    // asks for a takeback, or accepts the opponent's request
+    Takeback,
+}
+
+// chat belongs to the ply it was written at so it can go into the PGN after that move
+pub struct ChatMessage {
+    ply: usize,
+    color: Color,
+    text: String,
+}
+
+pub struct HistoryEntry {
+    board: [[Piece; 8]; 8],
+    last_move: Option<Move>,
+    text: String,
+    annotations: Vec<annotations::Annotation>,
+}
+
+// a move sliding from its start to its end square, the board is already the one after the move
+pub struct Animation {
+    before: [[Piece; 8]; 8],
+    mv: Move,
+    kind: board_utils::MoveKind,
+    started: Instant,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
-pub enum Backend {
-    Redkar,
-    Fritiofr,
-    Erikfran,
+// the channels to the network thread and which side we are on
+pub struct Session {
+    is_server: bool,
+    server_color: Color,
+    receiver: Receiver<TcpToGame>,
+    sender: Sender<GameToTcp>,
+    // the network thread is gone, the game can still be reviewed
+    closed: bool,
+}
+
+// setup -> connecting -> playing -> game over <-> review, and back to setup from the last two
+pub enum Screen {
+    Setup {
+        is_server: Option<bool>,
+        server_color: Option<Color>,
+        // a name from backends::BACKENDS
+        backend: &'static str,
+        ip: String,
+        error: Option<String>,
+    },
+    Connecting {
+        is_server: bool,
+        receiver: Receiver<TcpToGame>,
+        sender: Sender<GameToTcp>,
+    },
+    Playing(Session),
+    GameOver(Session),
+    Review {
+        session: Session,
+        index: usize,
+    },
+}
+
+impl Screen {
+    fn setup(error: Option<String>) -> Self {
+        Screen::Setup {
+            is_server: None,
+            server_color: None,
+            backend: backends::BACKENDS[0].name,
+            ip: local_address(),
+            error,
+        }
+    }
 }
 
 struct MainState {
@@ -102,6 +224,7 @@ struct MainState {
     black_selected_square: graphics::Mesh,
     white_moving_square: graphics::Mesh,
     black_moving_square: graphics::Mesh,
+    check_square: graphics::Mesh,
     selected: Option<Vec2>,
     dragging: bool,
     start_x: f32,
@@ -113,19 +236,36 @@ struct MainState {
     text: Text,
     joever: Joever,
     gui: Gui,
-    is_server: Option<bool>,
-    server_color: Option<chess_network_protocol::Color>,
-    tcp_started: bool,
-    receiver: Option<Receiver<TcpToGame>>,
-    sender: Option<Sender<GameToTcp>>,
+    screen: Screen,
     board: [[Piece; 8]; 8],
     moves: Vec<Move>,
+    waiting_moves: Vec<Move>,
     features: Vec<Features>,
     turn: Color,
-    ip: String,
     move_circle: graphics::Mesh,
     move_capture: graphics::Mesh,
-    backend: Backend,
+    move_input: String,
+    history: Vec<HistoryEntry>,
+    premoves: Vec<Move>,
+    premove_square: graphics::Mesh,
+    annotation_start: Option<(usize, usize)>,
+    pgn_annotations: bool,
+    rematch_asked: bool,
+    rematch_offered: bool,
+    takeback_asked: bool,
+    takeback_offered: bool,
+    draw_asked: bool,
+    draw_offered: bool,
+    // the next resync is the board after a takeback, not an error
+    taking_back: bool,
+    chat: Vec<ChatMessage>,
+    chat_input: String,
+    animation: Option<Animation>,
+    animation_duration: f32,
+    // a text box has the keyboard, key presses are typing and not shortcuts
+    typing: bool,
+    pending_move: Option<(Move, Instant)>,
+    pending_square: graphics::Mesh,
 }
 
 impl MainState {
@@ -205,6 +345,24 @@ impl MainState {
             Rect::new(0.0, 0.0, SQUARE_SIZE, SQUARE_SIZE),
             graphics::Color::from_rgb(133, 120, 78)
         )?;
+        let check_square = graphics::Mesh::new_rectangle(
+            ctx,
+            graphics::DrawMode::fill(),
+            Rect::new(0.0, 0.0, SQUARE_SIZE, SQUARE_SIZE),
+            graphics::Color::new(0.9, 0.15, 0.1, 0.6)
+        )?;
+        let premove_square = graphics::Mesh::new_rectangle(
+            ctx,
+            graphics::DrawMode::fill(),
+            Rect::new(0.0, 0.0, SQUARE_SIZE, SQUARE_SIZE),
+            graphics::Color::new(0.2, 0.35, 0.8, 0.45)
+        )?;
+        let pending_square = graphics::Mesh::new_rectangle(
+            ctx,
+            graphics::DrawMode::fill(),
+            Rect::new(0.0, 0.0, SQUARE_SIZE, SQUARE_SIZE),
+            graphics::Color::new(0.5, 0.5, 0.5, 0.5)
+        )?;
         
         let move_circle = graphics::Mesh::new_circle(
             ctx,
@@ -221,7 +379,7 @@ impl MainState {
             move_rgb_white,
         )?;
 
-        let mut controls_text = "Controls:\n\nHold left click and drag to move a piece and just release left click on the destination square to make the move.".to_string();
+        let controls_text = CONTROLS_TEXT.to_string();
 
         let mut gui =  Gui::new(ctx);
 
@@ -263,6 +421,7 @@ impl MainState {
             black_selected_square,
             white_moving_square,
             black_moving_square,
+            check_square,
             selected: None,
             dragging: false,
             start_x: 0.0,
@@ -274,177 +433,489 @@ impl MainState {
             text: Text::new(""),
             joever: Joever::Ongoing,
             gui,
-            is_server: None,
-            server_color: None,
-            tcp_started: false,
-            receiver: None,
-            sender: None,
+            screen: Screen::setup(None),
             board: [[Piece::None; 8]; 8],
             moves: vec![],
+            waiting_moves: vec![],
             features: vec![],
             turn: Color::White,
-            ip: local_ip().unwrap().to_string(),
             move_circle,
             move_capture,
-            backend: Backend::Redkar,
+            move_input: String::new(),
+            history: vec![],
+            premoves: vec![],
+            premove_square,
+            annotation_start: None,
+            pgn_annotations: true,
+            rematch_asked: false,
+            rematch_offered: false,
+            takeback_asked: false,
+            takeback_offered: false,
+            draw_asked: false,
+            draw_offered: false,
+            taking_back: false,
+            chat: vec![],
+            chat_input: String::new(),
+            animation: None,
+            animation_duration: ANIMATION_DURATION,
+            typing: false,
+            pending_move: None,
+            pending_square,
         };
 
         Ok(s)
     }
-}
 
-impl event::EventHandler<ggez::GameError> for MainState {
-	fn update(&mut self, ctx: &mut Context) -> GameResult {
-        let gui_ctx = self.gui.ctx();
+    fn apply_state(&mut self, board: [[Piece; 8]; 8], moves: Vec<Move>, joever: Joever, move_made: Move, turn: Color) {
+        self.animation = if self.animation_duration > 0.0 {
+            Some(Animation {
+                before: self.board,
+                mv: move_made,
+                kind: board_utils::classify_move(&self.board, &move_made),
+                started: Instant::now(),
+            })
+        }
+        else {
+            None
+        };
+        self.board = board;
+        self.moves = moves;
+        self.last_move = Some(move_made);
+        self.joever = joever;
+        let mut text = format!("{:?} moved {:?} from {} to {}",
+            self.turn, 
+            self.board[move_made.end_y][move_made.end_x],
+            cords_to_square(move_made.start_x as f32, move_made.start_y as f32), 
+            cords_to_square(move_made.end_x as f32, move_made.end_y as f32)
+        );
+        if joever == Joever::Ongoing && board_utils::in_check(&board, &turn) {
+            text += &format!("\n\nCheck! {:?} king is in danger", turn);
+        }
+        self.text = Text::new(text);
+        self.history.push(HistoryEntry {
+            board,
+            last_move: Some(move_made),
+            text: format!("{}{}",
+                cords_to_square(move_made.start_x as f32, move_made.start_y as f32),
+                cords_to_square(move_made.end_x as f32, move_made.end_y as f32)
+            ),
+            annotations: vec![],
+        });
+        self.turn = turn;
+        self.selected = None;
+        self.dragging = false;
 
-        if self.tcp_started && let Some(receiver) = &self.receiver {
-            if let Ok(message) = receiver.try_recv() {
-                match message {
-                    TcpToGame::Handshake { .. } => unreachable!(),
-                    TcpToGame::State { board, moves, joever, move_made, turn } => {
-                        self.board = board;
-                        self.moves = moves;
-                        self.last_move = Some(move_made);
-                        self.joever = joever;
-                        self.text = Text::new(
-                            format!("{:?} moved {:?} from {} to {}",
-                                self.turn, 
-                                self.board[move_made.end_y][move_made.end_x],
-                                cords_to_square(move_made.start_x as f32, move_made.start_y as f32), 
-                                cords_to_square(move_made.end_x as f32, move_made.end_y as f32)
-                            ));
-                        self.turn = turn;
-                        self.selected = None;
-                        self.dragging = false;
-                    },
-                    TcpToGame::Error { .. } => unreachable!(),
-                    TcpToGame::Draw { board, moves } => {
-                        self.board = board;
-                        self.moves = moves;
-                        self.joever = Joever::Draw;
-                        self.text = Text::new("Draw");
-                    },
-                    TcpToGame::Resigned { board, joever } => {
-                        self.board = board;
-                        self.joever = joever;
-                        self.text = Text::new("Resigned");
-                    },
-                }
+        if self.joever != Joever::Ongoing {
+            self.end_game();
+        }
+    }
+
+    fn session(&self) -> Option<&Session> {
+        match &self.screen {
+            Screen::Playing(session) | Screen::GameOver(session) | Screen::Review { session, .. } => Some(session),
+            Screen::Setup { .. } | Screen::Connecting { .. } => None,
+        }
+    }
+
+    fn session_mut(&mut self) -> Option<&mut Session> {
+        match &mut self.screen {
+            Screen::Playing(session) | Screen::GameOver(session) | Screen::Review { session, .. } => Some(session),
+            Screen::Setup { .. } | Screen::Connecting { .. } => None,
+        }
+    }
+
+    // leaves the current screen for the setup screen and hands back the session if there was one
+    fn take_session(&mut self) -> Option<Session> {
+        match std::mem::replace(&mut self.screen, Screen::setup(None)) {
+            Screen::Playing(session) | Screen::GameOver(session) | Screen::Review { session, .. } => Some(session),
+            Screen::Setup { .. } | Screen::Connecting { .. } => None,
+        }
+    }
+
+    // is_server and server_color, which together decide our color and the board orientation
+    fn side(&self) -> Option<(bool, Color)> {
+        self.session().map(|session| (session.is_server, session.server_color))
+    }
+
+    fn send(&self, message: GameToTcp) -> bool {
+        match self.session() {
+            Some(session) => session.sender.send(message).is_ok(),
+            None => false,
+        }
+    }
+
+    fn review_index(&self) -> Option<usize> {
+        match &self.screen {
+            Screen::Review { index, .. } => Some(*index),
+            _ => None,
+        }
+    }
+
+    fn end_game(&mut self) {
+        self.selected = None;
+        self.dragging = false;
+        self.premoves.clear();
+        self.pending_move = None;
+
+        if let Screen::Playing(_) = self.screen {
+            if let Some(session) = self.take_session() {
+                self.screen = Screen::GameOver(session);
             }
+        }
+    }
 
-            if your_turn(&self.turn, &self.server_color.unwrap(), self.is_server.unwrap()) {
-                let mut draw = false;
-                let mut resign = false;
-                
-                egui::Area::new("").movable(false).show(&gui_ctx, |ui| {
-                    ui.horizontal(|ui| {
-                        if ui.button("Draw").clicked() {
-                            draw = true;
-                        }
-                        if ui.button("Resign").clicked() {
-                            resign = true;
-                        }
-                    });
-                });
+    fn review(&mut self, index: usize) {
+        let index = index.min(self.history.len().saturating_sub(1));
 
-                if draw {
-                    self.sender.clone().unwrap().send(GameToTcp::Draw).unwrap();
-                }
+        if let Screen::Review { index: current, .. } = &mut self.screen {
+            *current = index;
+        }
+        else if let Screen::GameOver(_) = self.screen {
+            if let Some(session) = self.take_session() {
+                self.screen = Screen::Review { session, index };
             }
         }
-        if let Some(receiver) = &self.receiver && !self.tcp_started {
-            if let Ok(message) = receiver.try_recv() {
-                match message {
-                    TcpToGame::Handshake { board, moves, features, server_color } => {
-                        self.board = board;
-                        self.moves = moves;
+    }
 
-                        let mut features_text = "Features: ".to_string();
+    fn step_review(&mut self, step: i32) {
+        let last = self.history.len().saturating_sub(1);
+        let index = self.review_index().unwrap_or(last) as i32;
 
-                        for f in &features {
-                            features_text += match f {
-                                Features::Castling => &"Castling, ",
-                                Features::EnPassant => "En Passant, ",
-                                Features::Promotion => "Promotion, ",
-                                Features::PossibleMoveGeneration => "Possible Move Generation, ",
-                                Features::Stalemate => "Stalemate, ",
-                                Features::Other(f) => f.as_str(),
-                            }
-                        }
+        self.review((index + step).clamp(0, last as i32) as usize);
+    }
 
-                        self.controls_text = self.controls_text.clone() + "\n\n" + features_text.as_str();
+    // everything about the last game, used before a new one starts
+    fn reset_game(&mut self) {
+        self.board = [[Piece::None; 8]; 8];
+        self.moves = vec![];
+        self.waiting_moves = vec![];
+        self.features = vec![];
+        self.turn = Color::White;
+        self.joever = Joever::Ongoing;
+        self.last_move = None;
+        self.history.clear();
+        self.premoves.clear();
+        self.pending_move = None;
+        self.animation = None;
+        self.selected = None;
+        self.dragging = false;
+        self.rematch_asked = false;
+        self.rematch_offered = false;
+        self.takeback_asked = false;
+        self.takeback_offered = false;
+        self.draw_asked = false;
+        self.draw_offered = false;
+        self.taking_back = false;
+        self.chat.clear();
+        self.move_input.clear();
+        self.text = Text::new("");
+        self.controls_text = CONTROLS_TEXT.to_string();
+    }
 
-                        self.features = features;
-                        self.server_color = Some(server_color);
-                        self.tcp_started = true;
-                    },
-                    TcpToGame::State { .. } => unreachable!(),
-                    TcpToGame::Error { .. } => unreachable!(),
-                    TcpToGame::Draw { .. } => unreachable!(),
-                    TcpToGame::Resigned { .. } => unreachable!(),
-                }
+    // the board and last move that should be drawn, in review mode this is the ply being looked at
+    // also returns the board from before the last move so castling and en passant can be highlighted
+    fn displayed_position(&self) -> ([[Piece; 8]; 8], Option<Move>, Option<[[Piece; 8]; 8]>) {
+        let index = match self.review_index() {
+            Some(i) => i,
+            None => self.history.len().saturating_sub(1),
+        };
+        let previous = index.checked_sub(1).and_then(|i| self.history.get(i)).map(|entry| entry.board);
+
+        match self.review_index().and_then(|i| self.history.get(i)) {
+            Some(entry) => (entry.board, entry.last_move, previous),
+            None => (self.board, self.last_move, previous),
+        }
+    }
+
+    // captured pieces up to the position being shown, found by diffing the boards in the history
+    fn captured_pieces(&self) -> Vec<Piece> {
+        let end = match self.review_index() {
+            Some(i) => i + 1,
+            None => self.history.len(),
+        };
+
+        self.history[..end.min(self.history.len())]
+            .windows(2)
+            .flat_map(|w| board_utils::captured_between(&w[0].board, &w[1].board))
+            .collect()
+    }
+
+    fn draw_tray(&self, canvas: &mut graphics::Canvas, pieces: &[Piece], advantage: i32, pos: Vec2) {
+        let mut offset = 0.0;
+
+        for piece in pieces {
+            if let Some(image) = self.piece_image(piece) {
+                canvas.draw(image, graphics::DrawParam::new()
+                    .dest(pos + Vec2::new(offset, 0.0))
+                    .scale(Vec2::new(TRAY_SIZE / image.width() as f32, TRAY_SIZE / image.height() as f32)));
             }
+            offset += TRAY_SIZE * TRAY_SPACING;
+        }
 
-            if self.is_server.unwrap() {
-                egui::Area::new("").movable(false).show(&gui_ctx, |ui| {
-                    ui.label("Waiting for client to connect... \n Your ip: ".to_string() + local_ip().unwrap().to_string().as_str() + ":8384".as_str());
-                });
+        if advantage > 0 {
+            let mut text = Text::new(format!("+{}", advantage));
+            text.set_scale(PxScale::from(TEXT_SIZE));
+            canvas.draw(&text, pos + Vec2::new(offset + TRAY_SIZE, (TRAY_SIZE - TEXT_SIZE) / 2.0));
+        }
+    }
+
+    // annotations belong to the position they are drawn on, so they are gone after the next move
+    // but stay in the history for review and the PGN
+    fn annotations_mut(&mut self) -> Option<&mut Vec<annotations::Annotation>> {
+        let index = match self.review_index() {
+            Some(i) => i,
+            None => self.history.len().checked_sub(1)?,
+        };
+
+        self.history.get_mut(index).map(|entry| &mut entry.annotations)
+    }
+
+    fn draw_annotations(&self, ctx: &mut Context, canvas: &mut graphics::Canvas, is_server: bool, server_color: &Color) -> GameResult {
+        let index = match self.review_index() {
+            Some(i) => i,
+            None => match self.history.len().checked_sub(1) {
+                Some(i) => i,
+                None => return Ok(()),
+            },
+        };
+
+        let center = |(x, y): (usize, usize)| Vec2::new(
+            x_colored(is_server, server_color, x) as f32 * SQUARE_SIZE + SQUARE_SIZE / 2.0,
+            y_colored(is_server, server_color, y) as f32 * SQUARE_SIZE + SQUARE_SIZE / 2.0,
+        );
+
+        for annotation in &self.history[index].annotations {
+            let color = annotation.color.rgba();
+            let start = center(annotation.from);
+
+            if annotation.from == annotation.to {
+                let circle = graphics::Mesh::new_circle(ctx, graphics::DrawMode::stroke(SQUARE_SIZE * 0.08), start, SQUARE_SIZE * 0.45, 0.5, color)?;
+                canvas.draw(&circle, DrawParam::default());
+                continue;
+            }
+
+            let end = center(annotation.to);
+            let direction = (end - start).normalize();
+            let side = Vec2::new(-direction.y, direction.x);
+            let shaft_end = end - direction * ARROW_HEAD;
+
+            let shaft = graphics::Mesh::new_line(ctx, &[start, shaft_end], ARROW_WIDTH, color)?;
+            let head = graphics::Mesh::new_polygon(
+                ctx,
+                graphics::DrawMode::fill(),
+                &[end, shaft_end + side * ARROW_HEAD / 2.0, shaft_end - side * ARROW_HEAD / 2.0],
+                color,
+            )?;
+            canvas.draw(&shaft, DrawParam::default());
+            canvas.draw(&head, DrawParam::default());
+        }
+
+        Ok(())
+    }
+
+    fn draw_animation(&self, canvas: &mut graphics::Canvas, animation: &Animation, progress: f32, is_server: bool, server_color: &Color) {
+        let square = |(x, y): (usize, usize)| Vec2::new(
+            x_colored(is_server, server_color, x) as f32 * SQUARE_SIZE,
+            y_colored(is_server, server_color, y) as f32 * SQUARE_SIZE,
+        );
+        // ease out so the piece slows down as it lands
+        let t = 1.0 - (1.0 - progress.clamp(0.0, 1.0)).powi(2);
+        let mv = &animation.mv;
+
+        let captured = match animation.kind {
+            board_utils::MoveKind::EnPassant { captured } => Some(captured),
+            kind if kind.is_capture() => Some((mv.end_x, mv.end_y)),
+            _ => None,
+        };
+        if let Some((x, y)) = captured {
+            if let Some(image) = self.piece_image(&animation.before[y][x]) {
+                canvas.draw(image, graphics::DrawParam::new()
+                    .dest(square((x, y)))
+                    .scale(Vec2::new(0.75, 0.75))
+                    .color(graphics::Color::new(1.0, 1.0, 1.0, 1.0 - t)));
             }
         }
-        else if self.receiver.is_none() {
-            egui::Area::new("").movable(false).show(&gui_ctx, |ui| {
+
+        let mut sliding = vec![((mv.start_x, mv.start_y), (mv.end_x, mv.end_y))];
+        if let board_utils::MoveKind::Castle { rook_from, rook_to } = animation.kind {
+            sliding.push((rook_from, rook_to));
+        }
+
+        for (from, to) in sliding {
+            if let Some(image) = self.piece_image(&animation.before[from.1][from.0]) {
+                canvas.draw(image, graphics::DrawParam::new()
+                    .dest(square(from).lerp(square(to), t))
+                    .scale(Vec2::new(0.75, 0.75)));
+            }
+        }
+    }
+
+    fn piece_image(&self, piece: &Piece) -> Option<&graphics::Image> {
+        match piece {
+            Piece::WhitePawn => Some(&self.pawn_image_w),
+            Piece::BlackPawn => Some(&self.pawn_image_b),
+            Piece::WhiteKing => Some(&self.king_image_w),
+            Piece::BlackKing => Some(&self.king_image_b),
+            Piece::WhiteQueen => Some(&self.queen_image_w),
+            Piece::BlackQueen => Some(&self.queen_image_b),
+            Piece::WhiteBishop => Some(&self.bishop_image_w),
+            Piece::BlackBishop => Some(&self.bishop_image_b),
+            Piece::WhiteKnight => Some(&self.knight_image_w),
+            Piece::BlackKnight => Some(&self.knight_image_b),
+            Piece::WhiteRook => Some(&self.rook_image_w),
+            Piece::BlackRook => Some(&self.rook_image_b),
+            Piece::None => None,
+        }
+    }
+
+    // the board square under a point on the screen, None outside the board or before the game started
+    fn board_square(&self, x: f32, y: f32) -> Option<(usize, usize)> {
+        let (is_server, server_color) = self.side()?;
+
+        if x < 0.0 || y < 0.0 || x >= 8.0 * SQUARE_SIZE || y >= 8.0 * SQUARE_SIZE {
+            return None;
+        }
+
+        Some((
+            x_colored(is_server, &server_color, (x / SQUARE_SIZE).floor() as usize),
+            y_colored(is_server, &server_color, (y / SQUARE_SIZE).floor() as usize),
+        ))
+    }
+
+    // the board as it will look after the queued premoves, pieces are just moved without any rules
+    fn premove_board(&self) -> [[Piece; 8]; 8] {
+        let mut board = self.board;
+
+        for mv in &self.premoves {
+            board[mv.end_y][mv.end_x] = board[mv.start_y][mv.start_x];
+            board[mv.start_y][mv.start_x] = Piece::None;
+        }
+
+        board
+    }
+
+    // plays the first queued premove once it is our turn, or throws the queue away if it isn't legal anymore
+    fn play_premove(&mut self) {
+        if self.premoves.is_empty() || self.joever != Joever::Ongoing {
+            self.premoves.clear();
+            return;
+        }
+
+        let (is_server, server_color) = match self.side() {
+            Some(side) => side,
+            None => return,
+        };
+
+        if self.pending_move.is_some() || !your_turn(&self.turn, &server_color, is_server) {
+            return;
+        }
+
+        let mut mv = self.premoves.remove(0);
+        let same_squares = |m: &&Move| m.start_x == mv.start_x && m.start_y == mv.start_y && m.end_x == mv.end_x && m.end_y == mv.end_y;
+
+        // promotions are premoved as queen promotions
+        let promotion = piece_color(&self.board[mv.start_y][mv.start_x]).map(|color| queen(&color));
+        let found = self.moves.iter().filter(same_squares).find(|m| m.promotion == Piece::None || Some(m.promotion) == promotion).copied();
+        if let Some(m) = found {
+            mv = m;
+        }
+
+        let legal = if self.moves.is_empty() {
+            piece_color(&self.board[mv.start_y][mv.start_x]) == Some(self.turn)
+        }
+        else {
+            self.moves.contains(&mv)
+        };
+
+        if !legal {
+            self.text = Text::new(format!("Premove {}{} is not legal anymore, {} queued premove(s) discarded",
+                cords_to_square(mv.start_x as f32, mv.start_y as f32),
+                cords_to_square(mv.end_x as f32, mv.end_y as f32),
+                self.premoves.len() + 1
+            ));
+            self.premoves.clear();
+            return;
+        }
+
+        self.send_move(mv);
+    }
+
+    // the reply is picked up in update like every other message so the window never waits on the network
+    fn send_move(&mut self, mv: Move) {
+        let sent = self.send(GameToTcp::Move(mv));
+
+        self.selected = None;
+        self.dragging = false;
+
+        if !sent {
+            self.text = Text::new("Move error: The connection is closed");
+            return;
+        }
+
+        self.pending_move = Some((mv, Instant::now()));
+        self.text = Text::new(format!("Sending {}{}...",
+            cords_to_square(mv.start_x as f32, mv.start_y as f32),
+            cords_to_square(mv.end_x as f32, mv.end_y as f32)
+        ));
+    }
+
+    fn update_setup(&mut self, gui_ctx: &egui::Context) {
+        let mut connect = false;
+
+        if let Screen::Setup { is_server, server_color, backend, ip, error } = &mut self.screen {
+            let animation_duration = &mut self.animation_duration;
+
+            egui::Area::new("").movable(false).show(gui_ctx, |ui| {
+                if let Some(error) = error {
+                    ui.colored_label(egui::Color32::RED, error.as_str());
+                }
+
                 ui.label("Want to start a session as server or client?");
                 ui.horizontal(|ui| {
                     ui.selectable_value(
-                        &mut self.is_server, 
+                        is_server, 
                         Some(true), 
                         "Server"
                     );
                     ui.selectable_value(
-                        &mut self.is_server, 
+                        is_server, 
                         Some(false), 
                         "Client"
                     );
                 });
 
-                if Some(true) == self.is_server {
+                ui.add(egui::Slider::new(animation_duration, 0.0..=1.0).text("Animation seconds (0 is off)"));
+
+                if Some(true) == *is_server {
                     ui.label("Want backend do you want to use?");
                     ui.horizontal(|ui| {
-                        ui.selectable_value(
-                            &mut self.backend, 
-                            Backend::Redkar, 
-                            "Redkar"
-                        );
-                        ui.selectable_value(
-                            &mut self.backend, 
-                            Backend::Fritiofr, 
-                            "Fritiofr"
-                        );
-                        ui.selectable_value(
-                            &mut self.backend, 
-                            Backend::Erikfran, 
-                            "Erikfran"
-                        );
+                        for registered in backends::BACKENDS {
+                            ui.selectable_value(
+                                backend, 
+                                registered.name, 
+                                registered.name
+                            );
+                        }
                     });
                     ui.horizontal(|ui| {
-                        ui.label("Your ip: ".to_string() + local_ip().unwrap().to_string().as_str() + ":8384".as_str());
+                        ui.label("Your ip: ".to_string() + local_address().as_str() + ":8384".as_str());
                         
                         if ui.button("Copy").clicked() {
-                            ui.output_mut(|o| o.copied_text = local_ip().unwrap().to_string() + ":8384".as_str());
+                            ui.output_mut(|o| o.copied_text = local_address() + ":8384".as_str());
                         }
                     });
                 }
     
-                if Some(false) == self.is_server {
+                if Some(false) == *is_server {
                     ui.label("Want color do you want to play as?");
                     ui.horizontal(|ui| {
                         ui.selectable_value(
-                            &mut self.server_color, 
+                            server_color, 
                             Some(Color::Black), 
                             "White"
                         );
                         ui.selectable_value(
-                            &mut self.server_color, 
+                            server_color, 
                             Some(Color::White), 
                             "Black"
                         );
@@ -453,46 +924,623 @@ impl event::EventHandler<ggez::GameError> for MainState {
                     ui.label("What is the IP of your opponent?");
                     
                     ui.horizontal(|ui| {
-                        ui.add(egui::TextEdit::multiline(&mut self.ip).hint_text(local_ip().unwrap().to_string()));
+                        ui.add(egui::TextEdit::multiline(ip).hint_text(local_address()));
                         
                         if ui.button("Paste").clicked() {
-                            ui.output(|o| {  self.ip = (&o.copied_text).to_string()});
+                            ui.output(|o| { *ip = (&o.copied_text).to_string()});
                         }
                     });
                 }
     
                 ui.add_enabled_ui(
-                    self.server_color.is_some() || Some(true) == self.is_server, 
+                    server_color.is_some() || Some(true) == *is_server, 
                     |ui| {
                         if ui.button("Connect").clicked() {
-                            let (tcp_sender, tcp_receiver) = std::sync::mpsc::channel();
-                            let (game_sender, game_receiver) = std::sync::mpsc::channel();
-    
-                            if Some(true) == self.is_server {
-                                match self.backend {
-                                    Backend::Fritiofr => thread::spawn(move || server::run(tcp_sender, game_receiver, crate::fritiofr_chess_utils::Game::new())),
-                                    Backend::Redkar => thread::spawn(move || server::run(tcp_sender, game_receiver, crate::redkar_chess_utils::Game::new())),
-                                    Backend::Erikfran => thread::spawn(move || server::run(tcp_sender, game_receiver, crate::erikfran_chess_utils::Game::new())),
-                                };
-
-                                
-                            } else {
-                                let temp = self.server_color.clone().unwrap();
-                                let temp_ip = self.ip.clone();
-    
-                                thread::spawn(move || client::run(
-                                    tcp_sender, 
-                                    game_receiver, 
-                                    temp,
-                                    temp_ip.to_string()));
+                            connect = true;
+                        }
+                });
+            });
+        }
+
+        if connect {
+            self.connect();
+        }
+    }
+
+    // spawns the network thread and moves on to the connecting screen
+    fn connect(&mut self) {
+        let (is_server, server_color, backend, ip) = match &self.screen {
+            Screen::Setup { is_server: Some(is_server), server_color, backend, ip, .. } => (*is_server, *server_color, *backend, ip.clone()),
+            _ => return,
+        };
+
+        let (tcp_sender, tcp_receiver) = std::sync::mpsc::channel();
+        let (game_sender, game_receiver) = std::sync::mpsc::channel();
+
+        if is_server {
+            // the backends don't report draws, the rules layer does that for all of them
+            let new_game = match backends::find(backend) {
+                Some(registered) => registered.with_rules,
+                None => return,
+            };
+            thread::spawn(move || server::run(tcp_sender, game_receiver, new_game()));
+        }
+        else {
+            let server_color = match server_color {
+                Some(c) => c,
+                None => return,
+            };
+
+            thread::spawn(move || client::run(
+                tcp_sender, 
+                game_receiver, 
+                server_color,
+                ip));
+        }
+
+        self.screen = Screen::Connecting {
+            is_server,
+            receiver: tcp_receiver,
+            sender: game_sender,
+        };
+    }
+
+    // the features both sides support, shown under the controls
+    fn set_features(&mut self, features: Vec<Features>) {
+        let names: Vec<&str> = features.iter().map(extension::name).collect();
+
+        self.controls_text = CONTROLS_TEXT.to_string() + "\n\nFeatures: " + names.join(", ").as_str();
+        self.features = features;
+    }
+
+    // a new game, either the first one of the session or a rematch
+    fn start_game(&mut self, board: [[Piece; 8]; 8], moves: Vec<Move>, waiting_moves: Vec<Move>, features: Vec<Features>, server_color: Color) {
+        self.reset_game();
+        self.board = board;
+        self.moves = moves;
+        self.waiting_moves = waiting_moves;
+        self.history.push(HistoryEntry {
+            board,
+            last_move: None,
+            text: "Start".to_string(),
+            annotations: vec![],
+        });
+
+        self.set_features(features);
+
+        // keep the channels, only the colors change between games
+        let session = match std::mem::replace(&mut self.screen, Screen::setup(None)) {
+            Screen::Connecting { is_server, receiver, sender } => Session { is_server, server_color, receiver, sender, closed: false },
+            Screen::Playing(session) | Screen::GameOver(session) | Screen::Review { session, .. } => Session { server_color, ..session },
+            Screen::Setup { .. } => return,
+        };
+        self.screen = Screen::Playing(session);
+    }
+
+    fn update_connecting(&mut self, gui_ctx: &egui::Context) {
+        let (is_server, message) = match &self.screen {
+            Screen::Connecting { is_server, receiver, .. } => (*is_server, receiver.try_recv()),
+            _ => return,
+        };
+
+        match message {
+            Ok(TcpToGame::Handshake { board, moves, waiting_moves, features, server_color }) => {
+                self.start_game(board, moves, waiting_moves, features, server_color);
+                return;
+            },
+            // the network thread couldn't bind or connect
+            Ok(TcpToGame::Error { message }) => {
+                self.screen = Screen::setup(Some(message));
+                return;
+            },
+            Ok(_) => {},
+            Err(TryRecvError::Empty) => {},
+            Err(TryRecvError::Disconnected) => {
+                self.screen = Screen::setup(Some("Could not connect, check the ip and try again".to_string()));
+                return;
+            },
+        }
+
+        let mut cancel = false;
+
+        egui::Area::new("").movable(false).show(gui_ctx, |ui| {
+            if is_server {
+                ui.label("Waiting for client to connect... \n Your ip: ".to_string() + local_address().as_str() + ":8384".as_str());
+            }
+            else {
+                ui.label("Connecting to the server...");
+            }
+            if ui.button("Cancel").clicked() {
+                cancel = true;
+            }
+        });
+
+        // dropping the channels stops the server from waiting for a client and frees the port
+        if cancel {
+            self.screen = Screen::setup(None);
+        }
+    }
+
+    // handles whatever the network thread sent since the last frame
+    fn poll_network(&mut self) {
+        let message = match self.session() {
+            Some(session) => session.receiver.try_recv(),
+            None => return,
+        };
+
+        match message {
+            Ok(message) => match message {
+                TcpToGame::Handshake { board, moves, waiting_moves, features, server_color } => {
+                    self.start_game(board, moves, waiting_moves, features, server_color);
+                },
+                TcpToGame::State { board, moves, joever, move_made, turn, waiting_moves } => {
+                    // a draw offer lapses when the side it was made to moves
+                    if let Some((is_server, server_color)) = self.side() {
+                        if turn == your_color(&server_color, is_server) {
+                            self.draw_asked = false;
+                        }
+                        else {
+                            self.draw_offered = false;
+                        }
+                    }
+
+                    self.pending_move = None;
+                    self.takeback_asked = false;
+                    self.takeback_offered = false;
+                    self.apply_state(board, moves, joever, move_made, turn);
+                    self.waiting_moves = waiting_moves;
+                    self.play_premove();
+                },
+                TcpToGame::Error { message } => {
+                    self.pending_move = None;
+                    self.text = Text::new(
+                        format!("Move error: {}", message));
+                    self.premoves.clear();
+                    self.selected = None;
+                    self.dragging = false;
+                },
+                TcpToGame::Desync { message } => {
+                    self.text = Text::new(format!("Desync: {}", message));
+                },
+                TcpToGame::Resync { board, moves, joever, turn, waiting_moves, message } => {
+                    self.pending_move = None;
+                    self.premoves.clear();
+                    self.selected = None;
+                    self.dragging = false;
+                    self.text = if self.taking_back { Text::new(message) } else { Text::new(format!("Move error: {}", message)) };
+                    self.taking_back = false;
+
+                    // the boards only differ if a move got lost somewhere
+                    if self.history.last().map_or(true, |entry| entry.board != board) {
+                        self.history.push(HistoryEntry {
+                            board,
+                            last_move: None,
+                            text: "Resynced with the server".to_string(),
+                            annotations: vec![],
+                        });
+                        self.last_move = None;
+                    }
+
+                    self.board = board;
+                    self.moves = moves;
+                    self.waiting_moves = waiting_moves;
+                    self.turn = turn;
+                    self.joever = joever;
+
+                    if self.joever != Joever::Ongoing {
+                        self.end_game();
+                    }
+                },
+                TcpToGame::Draw { board, moves } => {
+                    self.board = board;
+                    self.moves = moves;
+                    self.joever = Joever::Draw;
+                    self.text = Text::new("Draw");
+                    self.end_game();
+                },
+                TcpToGame::Resigned { board, joever } => {
+                    self.board = board;
+                    self.joever = joever;
+                    self.text = Text::new("Resigned");
+                    self.end_game();
+                },
+                TcpToGame::RematchOffered => {
+                    self.rematch_offered = true;
+                    self.text = Text::new("Your opponent wants a rematch");
+                },
+                TcpToGame::TakebackOffered => {
+                    self.takeback_offered = true;
+                    self.text = Text::new("Your opponent asks for a takeback");
+                },
+                TcpToGame::DrawOffered => {
+                    self.draw_offered = true;
+                    self.text = Text::new("Your opponent offers a draw");
+                },
+                TcpToGame::DrawDeclined => {
+                    self.draw_asked = false;
+                    self.text = Text::new("Your opponent declined the draw");
+                },
+                TcpToGame::TakenBack(plies) => {
+                    // the start position always stays
+                    let keep = self.history.len().saturating_sub(plies).max(1);
+                    self.history.truncate(keep);
+                    self.last_move = self.history.last().and_then(|entry| entry.last_move);
+                    self.animation = None;
+                    self.takeback_asked = false;
+                    self.takeback_offered = false;
+                    self.taking_back = true;
+                },
+                TcpToGame::Features(features) => self.set_features(features),
+                TcpToGame::Chat(text) => {
+                    if let Some((is_server, server_color)) = self.side() {
+                        self.chat.push(ChatMessage {
+                            ply: self.history.len().saturating_sub(1),
+                            color: oposite_color(&your_color(&server_color, is_server)),
+                            text,
+                        });
+                    }
+                },
+                TcpToGame::Disconnected { message } => self.disconnected(Some(message)),
+            },
+            Err(TryRecvError::Empty) => {},
+            Err(TryRecvError::Disconnected) => self.disconnected(None),
+        }
+    }
+
+    // the network thread told why it stopped, or just stopped
+    fn disconnected(&mut self, message: Option<String>) {
+        match self.session_mut() {
+            Some(session) if !session.closed => session.closed = true,
+            _ => return,
+        }
+
+        if self.joever == Joever::Ongoing {
+            self.text = Text::new(message.unwrap_or("Lost the connection to the opponent".to_string()));
+            self.joever = Joever::Indeterminate;
+            self.end_game();
+        }
+        else {
+            self.text = Text::new(message.unwrap_or("Your opponent left".to_string()));
+        }
+        self.pending_move = None;
+    }
+
+    fn update_playing(&mut self, gui_ctx: &egui::Context) {
+        self.poll_network();
+
+        let (is_server, server_color) = match self.side() {
+            Some(side) => side,
+            None => return,
+        };
+
+        // the move stays pending, sending it again could play it twice once the first one arrives.
+        // the player can only keep waiting or leave, which closes the connection
+        if let Some((_, sent)) = self.pending_move {
+            if sent.elapsed().as_secs_f32() > MOVE_TIMEOUT {
+                let mut leave = false;
+
+                egui::Area::new("pending")
+                    .movable(false)
+                    .fixed_pos(egui::pos2(8.0 * SQUARE_SIZE + TEXT_SIZE, 8.0 * SQUARE_SIZE - TRAY_SIZE - 11.0 * TEXT_SIZE))
+                    .show(gui_ctx, |ui| {
+                        ui.label("The server hasn't answered the move yet");
+                        if ui.button("Leave the game").clicked() {
+                            leave = true;
+                        }
+                    });
+
+                if leave {
+                    self.screen = Screen::setup(Some("Left the game, the server didn't answer the move".to_string()));
+                    self.reset_game();
+                    return;
+                }
+            }
+        }
+
+        // resigning ends the game at once and is part of the protocol. a draw is offered on your turn
+        // if the other side negotiated draw offers, and only ends the game once it is accepted. the
+        // end comes back as TcpToGame::Draw or Resigned
+        let yours = your_turn(&self.turn, &server_color, is_server) && self.pending_move.is_none();
+
+        if self.joever == Joever::Ongoing && (yours || self.draw_offered) {
+            let mut draw = false;
+            let mut decline = false;
+            let mut resign = false;
+            let can_offer = yours && !self.draw_asked && extension::supports(&self.features, extension::DRAW_OFFERS);
+            
+            egui::Area::new("draw")
+                .movable(false)
+                .fixed_pos(egui::pos2(8.0 * SQUARE_SIZE + TEXT_SIZE, 8.0 * SQUARE_SIZE - TRAY_SIZE - 9.0 * TEXT_SIZE))
+                .show(gui_ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        if self.draw_offered {
+                            if ui.button("Accept draw").clicked() {
+                                draw = true;
                             }
-    
-                            self.receiver = Some(tcp_receiver);
-                            self.sender = Some(game_sender);
+                            if ui.button("Decline draw").clicked() {
+                                decline = true;
+                            }
+                        }
+                        else if can_offer && ui.button("Offer draw").clicked() {
+                            draw = true;
+                        }
+                        if yours && ui.button("Resign").clicked() {
+                            resign = true;
                         }
+                    });
+                });
+
+            if draw && self.send(GameToTcp::Draw) {
+                if self.draw_offered {
+                    self.draw_offered = false;
+                }
+                else {
+                    self.draw_asked = true;
+                    self.text = Text::new("Draw offered");
+                }
+            }
+            if decline && self.send(GameToTcp::DeclineDraw) {
+                self.draw_offered = false;
+                self.text = Text::new("Draw declined");
+            }
+            if resign {
+                self.send(GameToTcp::Resign);
+            }
+        }
+
+        if extension::supports(&self.features, extension::TAKEBACK) && self.joever == Joever::Ongoing {
+            let mut takeback = false;
+
+            egui::Area::new("takeback")
+                .movable(false)
+                .fixed_pos(egui::pos2(8.0 * SQUARE_SIZE + TEXT_SIZE, 8.0 * SQUARE_SIZE - TRAY_SIZE - 7.0 * TEXT_SIZE))
+                .show(gui_ctx, |ui| {
+                    ui.add_enabled_ui(self.history.len() > 1 && !self.takeback_asked, |ui| {
+                        let label = if self.takeback_offered { "Accept takeback" } else { "Request takeback" };
+
+                        if ui.button(label).clicked() {
+                            takeback = true;
+                        }
+                    });
+                });
+
+            if takeback && self.send(GameToTcp::Takeback) {
+                if self.takeback_offered {
+                    self.takeback_offered = false;
+                }
+                else {
+                    self.takeback_asked = true;
+                    self.text = Text::new("Takeback requested");
+                }
+            }
+        }
+
+        if !self.premoves.is_empty() {
+            let mut clear = false;
+
+            egui::Area::new("premoves")
+                .movable(false)
+                .fixed_pos(egui::pos2(8.0 * SQUARE_SIZE + TEXT_SIZE, 8.0 * SQUARE_SIZE - TRAY_SIZE - 5.0 * TEXT_SIZE))
+                .show(gui_ctx, |ui| {
+                    if ui.button(format!("Clear {} premove(s)", self.premoves.len())).clicked() {
+                        clear = true;
+                    }
+                });
+
+            if clear {
+                self.premoves.clear();
+                self.text = Text::new("Premoves cleared");
+            }
+        }
+
+        if extension::supports(&self.features, extension::CHAT) {
+            let mut send_chat = false;
+
+            egui::Area::new("chat")
+                .movable(false)
+                .fixed_pos(egui::pos2(8.0 * SQUARE_SIZE + TEXT_SIZE, 5.0 * SQUARE_SIZE))
+                .show(gui_ctx, |ui| {
+                    send_chat = self.chat_ui(ui, SQUARE_SIZE);
+                });
+
+            if send_chat {
+                self.send_chat();
+            }
+        }
+
+        let mut submit = false;
+
+        egui::Area::new("move_input")
+            .movable(false)
+            .fixed_pos(egui::pos2(8.0 * SQUARE_SIZE + TEXT_SIZE, 8.0 * SQUARE_SIZE - TRAY_SIZE - 3.0 * TEXT_SIZE))
+            .show(gui_ctx, |ui| {
+                ui.horizontal(|ui| {
+                    let response = ui.add(egui::TextEdit::singleline(&mut self.move_input).hint_text("e4, Nf3, O-O, e7e8q"));
+
+                    if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                        submit = true;
+                    }
+                    if ui.button("Move").clicked() {
+                        submit = true;
+                    }
                 });
             });
+
+        if submit && self.joever == Joever::Ongoing {
+            if !your_turn(&self.turn, &server_color, is_server) {
+                self.text = Text::new("Move input error: It is not your turn");
+            }
+            else if self.pending_move.is_some() {
+                self.text = Text::new("Move input error: Still waiting for the last move to be answered");
+            }
+            else {
+                match notation::parse_move(&self.move_input, &self.board, &self.moves, &self.turn) {
+                    Ok(mv) => {
+                        self.move_input.clear();
+                        self.send_move(mv);
+                    },
+                    Err(message) => {
+                        self.text = Text::new(format!("Move input error: {}", message));
+                    },
+                }
+            }
         }
+    }
+
+    // the chat pane, returns true when the typed message should be sent
+    fn chat_ui(&mut self, ui: &mut egui::Ui, height: f32) -> bool {
+        let mut send = false;
+        let you = self.side().map(|(is_server, server_color)| your_color(&server_color, is_server));
+
+        egui::CollapsingHeader::new(format!("Chat ({})", self.chat.len())).show(ui, |ui| {
+            egui::ScrollArea::vertical().max_height(height).stick_to_bottom(true).show(ui, |ui| {
+                for message in &self.chat {
+                    let name = if Some(message.color) == you { "You" } else { "Opponent" };
+                    ui.label(format!("{}: {}", name, message.text));
+                }
+            });
+            ui.horizontal(|ui| {
+                let response = ui.add(egui::TextEdit::singleline(&mut self.chat_input).hint_text("Say something"));
+
+                if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                    send = true;
+                }
+                if ui.button("Send").clicked() {
+                    send = true;
+                }
+            });
+        });
+
+        send
+    }
+
+    fn send_chat(&mut self) {
+        let text = self.chat_input.trim().to_string();
+
+        if text.is_empty() {
+            return;
+        }
+
+        if let Some((is_server, server_color)) = self.side() {
+            if self.send(GameToTcp::Chat(text.clone())) {
+                self.chat.push(ChatMessage {
+                    ply: self.history.len().saturating_sub(1),
+                    color: your_color(&server_color, is_server),
+                    text,
+                });
+                self.chat_input.clear();
+            }
+        }
+    }
+
+    fn update_game_over(&mut self, gui_ctx: &egui::Context) {
+        self.poll_network();
+
+        let last = self.history.len().saturating_sub(1);
+        let review_index = self.review_index().unwrap_or(last);
+        let mut jump = None;
+        let mut save_pgn = false;
+        let mut rematch = false;
+        let mut menu = false;
+        let can_rematch = self.session().map_or(false, |session| !session.closed) && extension::supports(&self.features, extension::REMATCH);
+        let chat = extension::supports(&self.features, extension::CHAT);
+        let mut send_chat = false;
+
+        egui::Area::new("review")
+            .movable(false)
+            .fixed_pos(egui::pos2(8.0 * SQUARE_SIZE + TEXT_SIZE, 4.0 * SQUARE_SIZE))
+            .show(gui_ctx, |ui| {
+                ui.label("Review the game with the arrow keys or the buttons");
+                ui.horizontal(|ui| {
+                    if ui.button("|<").clicked() {
+                        jump = Some(0);
+                    }
+                    if ui.button("<").clicked() {
+                        jump = Some(review_index.saturating_sub(1));
+                    }
+                    if ui.button(">").clicked() {
+                        jump = Some((review_index + 1).min(last));
+                    }
+                    if ui.button(">|").clicked() {
+                        jump = Some(last);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.pgn_annotations, "Arrows in PGN");
+                    if ui.button("Save PGN").clicked() {
+                        save_pgn = true;
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.add_enabled_ui(can_rematch && !self.rematch_asked, |ui| {
+                        let label = if self.rematch_offered { "Accept rematch" } else { "Rematch" };
+                        if ui.button(label).clicked() {
+                            rematch = true;
+                        }
+                    });
+                    if ui.button("Back to menu").clicked() {
+                        menu = true;
+                    }
+                });
+                egui::ScrollArea::vertical().max_height(1.5 * SQUARE_SIZE).show(ui, |ui| {
+                    for (i, entry) in self.history.iter().enumerate() {
+                        if ui.selectable_label(i == review_index, format!("{}. {}", i, entry.text)).clicked() {
+                            jump = Some(i);
+                        }
+                    }
+                });
+                if chat {
+                    send_chat = self.chat_ui(ui, SQUARE_SIZE / 2.0);
+                }
+            });
+
+        if let Some(index) = jump {
+            self.review(index);
+        }
+
+        if save_pgn {
+            let path = format!("game-{}.pgn", std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0));
+
+            self.text = match std::fs::write(&path, pgn::write_pgn(&self.history, &self.chat, &self.joever, self.pgn_annotations)) {
+                Ok(()) => Text::new(format!("Saved the game to {}", path)),
+                Err(e) => Text::new(format!("Could not save the game: {}", e)),
+            };
+        }
+
+        if send_chat {
+            self.send_chat();
+        }
+
+        if rematch && self.send(GameToTcp::Rematch) {
+            self.rematch_asked = true;
+            if !self.rematch_offered {
+                self.text = Text::new("Asked your opponent for a rematch");
+            }
+        }
+
+        // dropping the session closes the channels, which makes the network thread close the connection
+        if menu {
+            self.screen = Screen::setup(None);
+            self.reset_game();
+        }
+    }
+}
+
+impl event::EventHandler<ggez::GameError> for MainState {
+	fn update(&mut self, ctx: &mut Context) -> GameResult {
+        let gui_ctx = self.gui.ctx();
+
+        match self.screen {
+            Screen::Setup { .. } => self.update_setup(&gui_ctx),
+            Screen::Connecting { .. } => self.update_connecting(&gui_ctx),
+            Screen::Playing(_) => self.update_playing(&gui_ctx),
+            Screen::GameOver(_) | Screen::Review { .. } => self.update_game_over(&gui_ctx),
+        }
+
+        self.typing = gui_ctx.wants_keyboard_input();
 
 		self.gui.update(ctx);
 		Ok(())
@@ -504,23 +1552,71 @@ impl event::EventHandler<ggez::GameError> for MainState {
             graphics::Color::BLACK,
         );
 
-        if self.tcp_started && let Some(is_server) = self.is_server && let Some(server_color) = &self.server_color {
-            let mut moves = [[false; 8]; 8];
+        if let Some((is_server, server_color)) = self.side() {
+            let server_color = &server_color;
+            let (board, last_move, previous_board) = self.displayed_position();
+            let checked_king = board_utils::checked_king(&board);
+            let mut moves: [[Option<board_utils::MoveKind>; 8]; 8] = [[None; 8]; 8];
+            let mut castle_rooks = [[false; 8]; 8];
+            let mut promotions = [[Piece::None; 8]; 8];
+
+            // the waiting player sees the moves their pieces could make, drawn in the greyed out color
+            let hint_moves = if your_turn(&self.turn, server_color, is_server) { &self.moves } else { &self.waiting_moves };
 
             if let Some(pos) = self.selected {
-                for m in &self.moves {
+                for m in hint_moves {
                     if m.start_x == pos.x as usize && m.start_y == pos.y as usize {
-                        moves[m.end_y][m.end_x] = true;
+                        let kind = board_utils::classify_move(&board, m);
+
+                        if let board_utils::MoveKind::Castle { rook_from, .. } = kind {
+                            castle_rooks[rook_from.1][rook_from.0] = true;
+                        }
+                        // the queen is shown if the backend offers several pieces
+                        if m.promotion != Piece::None && !matches!(promotions[m.end_y][m.end_x], Piece::WhiteQueen | Piece::BlackQueen) {
+                            promotions[m.end_y][m.end_x] = m.promotion;
+                        }
+
+                        moves[m.end_y][m.end_x] = Some(kind);
                     }
                 }
             }
 
             let mut selected_image: Option<&graphics::Image> = None;
 
-            let (last_move_pos_from, last_move_pos_to) = match self.last_move {
-                Some(mv) => (Vec2::new(mv.start_x as f32, mv.start_y as f32), Vec2::new(mv.end_x as f32, mv.end_y as f32)),
-                None => (Vec2::new(-1.0, -1.0), Vec2::new(-1.0, -1.0)),
+            // pieces that are still sliding aren't drawn on their new squares yet
+            let at_latest = self.review_index().map_or(true, |i| i + 1 == self.history.len());
+            let progress = match &self.animation {
+                Some(animation) if at_latest => animation.started.elapsed().as_secs_f32() / self.animation_duration,
+                _ => 1.0,
             };
+            let mut hidden = [[false; 8]; 8];
+
+            if let (Some(animation), true) = (&self.animation, progress < 1.0) {
+                hidden[animation.mv.end_y][animation.mv.end_x] = true;
+
+                if let board_utils::MoveKind::Castle { rook_to, .. } = animation.kind {
+                    hidden[rook_to.1][rook_to.0] = true;
+                }
+            }
+
+            let mut moved_squares = [[false; 8]; 8];
+            let mut premove_squares = [[false; 8]; 8];
+
+            for mv in &self.premoves {
+                premove_squares[mv.start_y][mv.start_x] = true;
+                premove_squares[mv.end_y][mv.end_x] = true;
+            }
+
+            if let Some(mv) = last_move {
+                let squares = match previous_board {
+                    Some(previous) => board_utils::affected_squares(&previous, &mv),
+                    None => vec![(mv.start_x, mv.start_y), (mv.end_x, mv.end_y)],
+                };
+
+                for (x, y) in squares {
+                    moved_squares[y][x] = true;
+                }
+            }
 
             for x in 0..8 {
                 for y in 0..8 {
@@ -529,17 +1625,16 @@ impl event::EventHandler<ggez::GameError> for MainState {
                     let pos = Vec2::new(x_c as f32 * SQUARE_SIZE, y_c as f32 * SQUARE_SIZE);
                     let pos_unit = Vec2::new(x as f32, y as f32);
                     let selected = 
-                        self.selected == Some(pos_unit);
+                        self.selected == Some(pos_unit)
+                        || castle_rooks[y][x];
 
-                    let moved = 
-                        pos_unit == last_move_pos_from 
-                        || pos_unit == last_move_pos_to;
+                    let moved = moved_squares[y][x];
                     
                     let moving = 
                         x_colored(is_server, server_color, (self.pos_x / SQUARE_SIZE).floor() as usize) == pos_unit.x as usize
                         && y_colored(is_server, server_color, (self.pos_y / SQUARE_SIZE).floor() as usize) == pos_unit.y as usize
                         && self.selected.is_some()
-                        && piece_color(&self.board[y][x]) != Some(your_color(server_color, is_server))
+                        && piece_color(&board[y][x]) != Some(your_color(server_color, is_server))
                         && your_turn(&self.turn, server_color, is_server);
 
 /*                     let text_pos_y = Vec2::new((x as f32 + 1.0) * SQUARE_SIZE - CORD_OFFSET, 8.0 * SQUARE_SIZE - CORD_OFFSET);
@@ -560,7 +1655,7 @@ impl event::EventHandler<ggez::GameError> for MainState {
                         else {
                             canvas.draw(&self.white_square, pos);
                         }
-                        if moves[y][x] {
+                        if moves[y][x].is_some() {
                             if your_turn(&self.turn, server_color, is_server) {
                                 move_color = Some(self.move_rgb_white)
                             }
@@ -614,7 +1709,7 @@ impl event::EventHandler<ggez::GameError> for MainState {
                             let text_pos = text_pos_x;
                             canvas.draw(&text, text_pos);
                         } */
-                        if moves[y][x] {
+                        if moves[y][x].is_some() {
                             if your_turn(&self.turn, server_color, is_server) {
                                 move_color = Some(self.move_rgb_black)
                             }
@@ -624,33 +1719,62 @@ impl event::EventHandler<ggez::GameError> for MainState {
                         }
                     }
 
-                    if let Some(color) = move_color {
-                        if self.board[y][x] == Piece::None {
+                    if premove_squares[y][x] {
+                        canvas.draw(&self.premove_square, pos);
+                    }
+
+                    if let Some((mv, _)) = self.pending_move {
+                        if (mv.start_x, mv.start_y) == (x, y) || (mv.end_x, mv.end_y) == (x, y) {
+                            canvas.draw(&self.pending_square, pos);
+                        }
+
+                        // the piece the pending promotion asked for
+                        if (mv.end_x, mv.end_y) == (x, y) {
+                            if let Some(image) = self.piece_image(&mv.promotion) {
+                                canvas.draw(image, graphics::DrawParam::new()
+                                    .dest(pos + Vec2::new(SQUARE_SIZE * 0.6, 0.0))
+                                    .scale(Vec2::new(0.3, 0.3))
+                                    .color(graphics::Color::new(1.0, 1.0, 1.0, 0.7)));
+                            }
+                        }
+                    }
+
+                    if checked_king == Some((x, y)) {
+                        canvas.draw(&self.check_square, pos);
+                    }
+
+                    if let (Some(color), Some(kind)) = (move_color, moves[y][x]) {
+                        if kind.is_capture() {
+                            draw_captured_move(&mut canvas, pos, &color, &self.move_capture)
+                        } else {
                             canvas.draw(&self.move_circle, graphics::DrawParam::new()
                                 .dest(pos + Vec2::new(SQUARE_SIZE / 2.0, SQUARE_SIZE / 2.0))
                                 .color(color));
-                        } else {
-                            draw_captured_move(&mut canvas, pos, &color, &self.move_capture)
+                        }
+
+                        // show which piece the pawn turns into
+                        let promotion = match kind {
+                            board_utils::MoveKind::Promotion { .. } => self.piece_image(&promotions[y][x]),
+                            _ => None,
+                        };
+                        if let Some(image) = promotion {
+                            canvas.draw(image, graphics::DrawParam::new()
+                                .dest(pos + Vec2::new(SQUARE_SIZE * 0.6, 0.0))
+                                .scale(Vec2::new(0.3, 0.3))
+                                .color(graphics::Color::new(1.0, 1.0, 1.0, 0.7)));
                         }
                     }
 
-                    let image = match self.board[y][x] {
-                        Piece::WhitePawn => &self.pawn_image_w,
-                        Piece::BlackPawn => &self.pawn_image_b,
-                        Piece::WhiteKing => &self.king_image_w,
-                        Piece::BlackKing => &self.king_image_b,
-                        Piece::WhiteQueen => &self.queen_image_w,
-                        Piece::BlackQueen => &self.queen_image_b,
-                        Piece::WhiteBishop => &self.bishop_image_w,
-                        Piece::BlackBishop => &self.bishop_image_b,
-                        Piece::WhiteKnight => &self.knight_image_w,
-                        Piece::BlackKnight => &self.knight_image_b,
-                        Piece::WhiteRook => &self.rook_image_w,
-                        Piece::BlackRook => &self.rook_image_b,
-                        Piece::None => continue,
+                    if hidden[y][x] {
+                        continue;
+                    }
+
+                    let image = match self.piece_image(&board[y][x]) {
+                        Some(image) => image,
+                        None => continue,
                     };
 
-                    if self.selected == Some(pos_unit) && self.dragging && your_turn(&self.turn, server_color, is_server) && Some(your_color(server_color, is_server)) == piece_color(&self.board[y][x]) {
+                    if self.selected == Some(pos_unit) && self.dragging && your_turn(&self.turn, server_color, is_server) && Some(your_color(server_color, is_server)) == piece_color(&board[y][x]) {
                         selected_image = Some(image);
                     }
                     else {
@@ -661,6 +1785,12 @@ impl event::EventHandler<ggez::GameError> for MainState {
                 }
             }
 
+            if let (Some(animation), true) = (&self.animation, progress < 1.0) {
+                self.draw_animation(&mut canvas, animation, progress, is_server, server_color);
+            }
+
+            self.draw_annotations(ctx, &mut canvas, is_server, server_color)?;
+
             if let Some(selected_image) = selected_image {
                 canvas.draw(selected_image, graphics::DrawParam::new()
                     .dest(Vec2::new(self.pos_x - SQUARE_SIZE / 2.0, self.pos_y - SQUARE_SIZE / 2.0))
@@ -677,14 +1807,27 @@ impl event::EventHandler<ggez::GameError> for MainState {
             let _ = controls_text.set_bounds(Vec2::new(SIDEBAR_SIZE - TEXT_SIZE * 2.0, f32::INFINITY))
             .set_wrap(true);
 
-            let controls_text_pos = Vec2::new(8.0 * SQUARE_SIZE + TEXT_SIZE, TEXT_SIZE);
+            // the opponent's captures go at the top of the sidebar and yours at the very bottom, like the
+            // board. the move input and the buttons above it leave room for the bottom tray
+            let you = your_color(server_color, is_server);
+            let captured = self.captured_pieces();
+            let advantage = board_utils::material(&board, &you) - board_utils::material(&board, &oposite_color(&you));
+
+            let theirs: Vec<Piece> = captured.iter().filter(|p| piece_color(p) == Some(you)).copied().collect();
+            let yours: Vec<Piece> = captured.iter().filter(|p| piece_color(p) != Some(you)).copied().collect();
+
+            self.draw_tray(&mut canvas, &theirs, -advantage, Vec2::new(8.0 * SQUARE_SIZE + TEXT_SIZE, 0.0));
+            self.draw_tray(&mut canvas, &yours, advantage, Vec2::new(8.0 * SQUARE_SIZE + TEXT_SIZE, 8.0 * SQUARE_SIZE - TRAY_SIZE - TEXT_SIZE / 2.0));
+
+            let controls_text_pos = Vec2::new(8.0 * SQUARE_SIZE + TEXT_SIZE, TRAY_SIZE + TEXT_SIZE);
             let text_pos = controls_text_pos + 
                 Vec2::new( 0.0, controls_text.measure(ctx).unwrap().y + TEXT_SIZE);
 
             canvas.draw(&controls_text, controls_text_pos);
             canvas.draw(&self.text, text_pos);
 
-            if &self.joever != &Joever::Ongoing {
+            // only show the result on the final position so it doesn't cover the review
+            if &self.joever != &Joever::Ongoing && self.review_index().map_or(true, |i| i + 1 == self.history.len()) {
                 let mut text = Text::new("");
                 match &self.joever {
                     Joever::Black => {
@@ -717,6 +1860,28 @@ impl event::EventHandler<ggez::GameError> for MainState {
         Ok(())
     }
 
+    fn key_down_event(&mut self, _ctx: &mut Context, input: KeyInput, _repeated: bool) -> GameResult {
+        if self.typing {
+            return Ok(());
+        }
+
+        match input.keycode {
+            Some(KeyCode::Space) | Some(KeyCode::Escape) => self.animation = None,
+            Some(KeyCode::Left) => self.step_review(-1),
+            Some(KeyCode::Right) => self.step_review(1),
+            Some(KeyCode::Home) => self.step_review(i32::MIN / 2),
+            Some(KeyCode::End) => self.step_review(i32::MAX / 2),
+            _ => {},
+        }
+        Ok(())
+    }
+
+    fn text_input_event(&mut self, _ctx: &mut Context, character: char) -> GameResult {
+        // egui only gets typed characters if we forward them
+        self.gui.input.text_input_event(character);
+        Ok(())
+    }
+
     fn mouse_motion_event(
         &mut self,
         ctx: &mut Context,
@@ -741,21 +1906,30 @@ impl event::EventHandler<ggez::GameError> for MainState {
         x: f32,
         y: f32,
     ) -> GameResult {
-        if self.tcp_started 
-            && let Some(is_server) = self.is_server 
-            && let Some(server_color) = &self.server_color 
-            && x < 8.0 * SQUARE_SIZE 
+        self.animation = None;
+
+        if button == MouseButton::Right {
+            self.annotation_start = self.board_square(x, y);
+            return Ok(());
+        }
+
+        let on_board = x < 8.0 * SQUARE_SIZE 
             && y < 8.0 * SQUARE_SIZE 
             && x > 0.0
             && y > 0.0
-            && button == MouseButton::Left 
-            {
+            && button == MouseButton::Left;
+
+        if let Some((is_server, server_color)) = self.side().filter(|_| on_board) {
+            let server_color = &server_color;
             let y_c = y_colored(is_server, server_color, (y as f32 / SQUARE_SIZE).floor() as usize);
             let x_c = x_colored(is_server, server_color, (x as f32 / SQUARE_SIZE).floor() as usize);
 
             let temp = Some(Vec2::new(x_c as f32, y_c as f32));
             
-            if piece_color(&self.board[y_c as usize][x_c as usize]) != Some(your_color(server_color, is_server)) {
+            // premoves can start from squares your earlier premoves moved a piece to
+            let board = if your_turn(&self.turn, server_color, is_server) { self.board } else { self.premove_board() };
+
+            if piece_color(&board[y_c as usize][x_c as usize]) != Some(your_color(server_color, is_server)) {
                 return Ok(());
             }
 
@@ -776,15 +1950,32 @@ impl event::EventHandler<ggez::GameError> for MainState {
 
     fn mouse_button_up_event(
         &mut self,
-        _ctx: &mut Context,
+        ctx: &mut Context,
         button: MouseButton,
         x: f32,
         y: f32,
     ) -> GameResult {
+        if button == MouseButton::Right {
+            let start = self.annotation_start.take();
+
+            if let (Some(from), Some(to)) = (start, self.board_square(x, y)) {
+                let color = annotations::AnnotationColor::from_modifiers(
+                    ctx.keyboard.is_mod_active(KeyMods::SHIFT),
+                    ctx.keyboard.is_mod_active(KeyMods::CTRL),
+                    ctx.keyboard.is_mod_active(KeyMods::ALT),
+                );
+
+                if let Some(list) = self.annotations_mut() {
+                    annotations::toggle(list, annotations::Annotation { from, to, color });
+                }
+            }
+            return Ok(());
+        }
+
         self.dragging = false;
 
         if self.joever != Joever::Ongoing 
-            || !self.tcp_started 
+            || self.side().is_none() 
             || x > 8.0 * SQUARE_SIZE 
             || y > 8.0 * SQUARE_SIZE 
             || self.selected.is_none() 
@@ -793,7 +1984,8 @@ impl event::EventHandler<ggez::GameError> for MainState {
             return Ok(());
         }
 
-        if let Some(is_server) = self.is_server && let Some(server_color) = &self.server_color && let Some(selected) = self.selected {
+        if let (Some((is_server, server_color)), Some(selected)) = (self.side(), self.selected) {
+            let server_color = &server_color;
             let y_c = y_colored(is_server, server_color, (y as f32 / SQUARE_SIZE).floor() as usize);
             let x_c = x_colored(is_server, server_color, (x as f32 / SQUARE_SIZE).floor() as usize);
 
@@ -805,53 +1997,29 @@ impl event::EventHandler<ggez::GameError> for MainState {
                 promotion: Piece::None,
             };
 
-            if !your_turn(&self.turn, server_color, is_server) 
-                || Some(oposite_color(&your_color(server_color, is_server))) == piece_color(&self.board[selected.y as usize][selected.x as usize])
-                || (mv.end_x == mv.start_x && mv.end_y == mv.start_y) {
+            if mv.end_x == mv.start_x && mv.end_y == mv.start_y {
                 return Ok(());
             }
 
-            if let Some(sender) = &self.sender {
-                sender.send(GameToTcp::Move(mv)).unwrap();
-            }
-            if let Some(receiver) = &self.receiver {
-                match receiver.recv().unwrap() {
-                    TcpToGame::State { board, moves, joever, move_made, turn } => {
-                        self.board = board;
-                        self.last_move = Some(move_made);
-                        self.joever = joever;
-                        self.text = Text::new(
-                            format!("{:?} moved {:?} from {} to {}",
-                                self.turn, 
-                                self.board[move_made.end_y][move_made.end_x],
-                                cords_to_square(move_made.start_x as f32, move_made.start_y as f32), 
-                                cords_to_square(move_made.end_x as f32, move_made.end_y as f32)
-                            ));
-                        self.turn = turn;
-                        self.moves = moves;
-                        self.selected = None;
-                        self.dragging = false;
-                    },
-                    TcpToGame::Error { message } => {
-                        self.text = Text::new(
-                            format!("Move error: {}", message));
-                        self.selected = None;
-                        self.dragging = false;
-                    },
-                    TcpToGame::Handshake { .. } => unreachable!(),
-                    TcpToGame::Draw { board, moves } => {
-                        self.board = board;
-                        self.moves = moves;
-                        self.joever = Joever::Draw;
-                        self.text = Text::new("Draw");
-                    },
-                    TcpToGame::Resigned { board, joever } => {
-                        self.board = board;
-                        self.joever = joever;
-                        self.text = Text::new("Resigned");
-                    },
+            // while waiting the move is queued as a premove and played when the turn flips
+            if !your_turn(&self.turn, server_color, is_server) {
+                if Some(your_color(server_color, is_server)) == piece_color(&self.premove_board()[mv.start_y][mv.start_x]) {
+                    self.premoves.push(mv);
+                    self.text = Text::new(format!("Premove {}{} queued",
+                        cords_to_square(mv.start_x as f32, mv.start_y as f32),
+                        cords_to_square(mv.end_x as f32, mv.end_y as f32)
+                    ));
                 }
+                self.selected = None;
+                return Ok(());
             }
+
+            if Some(oposite_color(&your_color(server_color, is_server))) == piece_color(&self.board[selected.y as usize][selected.x as usize])
+                || self.pending_move.is_some() {
+                return Ok(());
+            }
+
+            self.send_move(mv);
             self.selected = None;
         }
         Ok(())
@@ -917,6 +2085,13 @@ fn oposite_color(color: &Color) -> Color {
     }
 }
 
+fn queen(color: &Color) -> Piece {
+    match color {
+        Color::White => Piece::WhiteQueen,
+        Color::Black => Piece::BlackQueen,
+    }
+}
+
 fn piece_color(piece: &Piece) -> Option<Color> {
     match piece {
         Piece::WhitePawn => Some(Color::White),
@@ -977,11 +2152,31 @@ fn cords_to_square(x: f32, y: f32) -> String {
     cord_to_file(x) + (y + 1.0).to_string().as_str()
 }
 
+// the ip of this machine, a machine without a network can still play against itself
+fn local_address() -> String {
+    local_ip().map(|ip| ip.to_string()).unwrap_or_else(|_| "127.0.0.1".to_string())
+}
+
 mod client;
 mod server;
 
 pub fn main() -> GameResult {
     std::env::set_var("RUST_BACKTRACE", "1");
+
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(|a| a.as_str()) == Some("replay") {
+        replay::run(&args[2..]);
+        return Ok(());
+    }
+    if args.get(1).map(|a| a.as_str()) == Some("differential") {
+        differential::run(&args[2..]);
+        return Ok(());
+    }
+    if args.get(1).map(|a| a.as_str()) == Some("perft") {
+        perft::run(&args[2..]);
+        return Ok(());
+    }
+
     let resource_dir = if let Ok(manifest_dir) = env::var("CARGO_MANIFEST_DIR") {
         let mut path = path::PathBuf::from(manifest_dir);
         path.push("resources");
@@ -0,0 +1,657 @@
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use chess_network_protocol::*;
+
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Sender, Receiver};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::{TcpToGame, GameToTcp, server, client, extension, erikfran_chess_utils};
+use crate::extension::Extension;
+use crate::rules::Rules;
+use crate::server::UniversalGame;
+use crate::test_utils::mv;
+
+// server::serve and client::connect against a scripted peer over loopback, with this module
+// standing in for the gui on the other end of their channels. the threads under test return when
+// the mock peer hangs up at the end of a test, the same way they do when a real opponent leaves
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// the other side of a session, speaking chess-network-protocol like another team's program would
+pub struct MockPeer {
+    stream: TcpStream,
+}
+
+impl MockPeer {
+    pub fn connect(address: &str) -> Self {
+        let stream = TcpStream::connect(address).expect("the mock peer couldn't connect");
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+
+        MockPeer { stream }
+    }
+
+    pub fn accept(listener: &TcpListener) -> Self {
+        let (stream, _addr) = listener.accept().expect("nothing connected to the mock peer");
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+
+        MockPeer { stream }
+    }
+
+    pub fn send<T: Serialize>(&self, message: &T) {
+        serde_json::to_writer(&self.stream, message).expect("the mock peer couldn't send");
+    }
+
+    // for what a broken peer would send
+    pub fn send_raw(&self, bytes: &[u8]) {
+        (&self.stream).write_all(bytes).expect("the mock peer couldn't send");
+    }
+
+    pub fn receive<T: DeserializeOwned>(&self) -> T {
+        let mut de = serde_json::Deserializer::from_reader(&self.stream);
+        T::deserialize(&mut de).unwrap_or_else(|e| panic!("the peer didn't get the expected message: {}", e))
+    }
+}
+
+// the gui end of the channels of the thread under test
+struct Gui {
+    sender: Sender<GameToTcp>,
+    receiver: Receiver<TcpToGame>,
+    thread: JoinHandle<()>,
+}
+
+impl Gui {
+    fn send(&self, message: GameToTcp) {
+        self.sender.send(message).expect("the network thread is gone");
+    }
+
+    fn receive(&self) -> TcpToGame {
+        self.receiver.recv_timeout(TIMEOUT).expect("the gui got nothing")
+    }
+
+    // skips whatever came before the disconnect and returns its message
+    fn receive_disconnect(&self) -> String {
+        loop {
+            if let TcpToGame::Disconnected { message } = self.receive() {
+                return message;
+            }
+        }
+    }
+
+    // waits for the thread to return, only call this once it was told to stop
+    fn join(self) {
+        assert!(self.thread.join().is_ok(), "the network thread panicked");
+    }
+}
+
+// a server whose client (the mock peer) plays white, after the handshake
+fn server_session() -> (Gui, MockPeer, ServerToClientHandshake) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let (tcp_sender, tcp_receiver) = mpsc::channel();
+    let (game_sender, game_receiver) = mpsc::channel();
+
+    let thread = thread::spawn(move || server::serve(listener, tcp_sender, game_receiver, Box::new(Rules::<erikfran_chess_utils::Game>::new())));
+
+    let gui = Gui { sender: game_sender, receiver: tcp_receiver, thread };
+    let peer = MockPeer::connect(&address);
+
+    peer.send(&ClientToServerHandshake { server_color: Color::Black });
+    let handshake: ServerToClientHandshake = peer.receive();
+
+    assert!(matches!(gui.receive(), TcpToGame::Handshake { server_color: Color::Black, .. }), "the server's gui didn't get the handshake");
+
+    (gui, peer, handshake)
+}
+
+fn expect_state(peer: &MockPeer, expected: &Move) -> ([[Piece; 8]; 8], Joever) {
+    match peer.receive() {
+        ServerToClient::State { board, joever, move_made, .. } => {
+            assert_eq!(move_made, *expected, "the state is for another move");
+            (board, joever)
+        },
+        ServerToClient::Error { message, .. } => panic!("expected a state, got the error \"{}\"", message),
+        _ => panic!("expected a state"),
+    }
+}
+
+fn expect_error(peer: &MockPeer, expected: &str) {
+    match peer.receive() {
+        ServerToClient::Error { message, .. } => assert!(message.contains(expected), "expected an error about \"{}\", got \"{}\"", expected, message),
+        _ => panic!("expected an error about \"{}\"", expected),
+    }
+}
+
+#[test]
+fn server_handshake() {
+    let (_gui, _peer, handshake) = server_session();
+
+    assert_eq!(handshake.joever, Joever::Ongoing);
+    assert_eq!(handshake.board[1][4], Piece::WhitePawn);
+    assert_eq!(handshake.board[6][4], Piece::BlackPawn);
+    assert!(!handshake.moves.is_empty(), "white should have moves");
+    assert!(extension::supports(&handshake.features, extension::CHAT), "the extensions should be advertised");
+}
+
+#[test]
+fn server_legal_move() {
+    let (gui, peer, _) = server_session();
+
+    peer.send(&ClientToServer::Move(mv("e2", "e4")));
+    let (board, joever) = expect_state(&peer, &mv("e2", "e4"));
+
+    assert_eq!(board[3][4], Piece::WhitePawn);
+    assert_eq!(board[1][4], Piece::None);
+    assert_eq!(joever, Joever::Ongoing);
+    assert!(matches!(gui.receive(), TcpToGame::State { turn: Color::Black, .. }), "the server's gui should get the state with black to move");
+}
+
+#[test]
+fn server_illegal_move() {
+    let (_gui, peer, _) = server_session();
+
+    // the backend's own message, whatever it says
+    peer.send(&ClientToServer::Move(mv("e2", "e5")));
+    expect_error(&peer, "");
+
+    // and the game goes on
+    peer.send(&ClientToServer::Move(mv("e2", "e4")));
+    expect_state(&peer, &mv("e2", "e4"));
+}
+
+#[test]
+fn server_outside_board() {
+    let (_gui, peer, _) = server_session();
+
+    peer.send(&ClientToServer::Move(Move { start_x: 4, start_y: 1, end_x: 4, end_y: 8, promotion: Piece::None }));
+    expect_error(&peer, "The move ends outside the board");
+}
+
+#[test]
+fn server_no_piece() {
+    let (_gui, peer, _) = server_session();
+
+    peer.send(&ClientToServer::Move(mv("e3", "e4")));
+    expect_error(&peer, "There is no piece on e3");
+}
+
+#[test]
+fn server_wrong_color() {
+    let (_gui, peer, _) = server_session();
+
+    peer.send(&ClientToServer::Move(mv("e7", "e5")));
+    expect_error(&peer, "The piece on e7 is Black, you play White");
+}
+
+#[test]
+fn server_out_of_turn() {
+    let (_gui, peer, _) = server_session();
+
+    peer.send(&ClientToServer::Move(mv("e2", "e4")));
+    expect_state(&peer, &mv("e2", "e4"));
+
+    peer.send(&ClientToServer::Move(mv("d2", "d4")));
+    expect_error(&peer, "It is Black's turn, not White's");
+}
+
+#[test]
+fn server_game_over() {
+    let (gui, peer, _) = server_session();
+
+    // fool's mate, the server's moves come from its gui
+    peer.send(&ClientToServer::Move(mv("f2", "f3")));
+    expect_state(&peer, &mv("f2", "f3"));
+    gui.send(GameToTcp::Move(mv("e7", "e5")));
+    expect_state(&peer, &mv("e7", "e5"));
+    peer.send(&ClientToServer::Move(mv("g2", "g4")));
+    expect_state(&peer, &mv("g2", "g4"));
+    gui.send(GameToTcp::Move(mv("d8", "h4")));
+    let (_, joever) = expect_state(&peer, &mv("d8", "h4"));
+
+    assert_eq!(joever, Joever::Black);
+
+    peer.send(&ClientToServer::Move(mv("e2", "e4")));
+    expect_error(&peer, "The game is already over");
+}
+
+#[test]
+fn server_draw() {
+    let (gui, peer, _) = server_session();
+
+    peer.send(&ClientToServer::Move(mv("e2", "e4")));
+    expect_state(&peer, &mv("e2", "e4"));
+    gui.send(GameToTcp::Draw);
+
+    assert!(matches!(peer.receive(), Extension::DrawOffer), "expected a draw offer");
+
+    // the client accepts
+    peer.send(&ClientToServer::Draw);
+
+    match peer.receive() {
+        ServerToClient::Draw { board, .. } => assert_eq!(board[3][4], Piece::WhitePawn, "the draw should come with the current board"),
+        _ => panic!("the server should end the game as a draw"),
+    }
+}
+
+#[test]
+fn server_resign() {
+    let (gui, peer, _) = server_session();
+
+    peer.send(&ClientToServer::Move(mv("e2", "e4")));
+    expect_state(&peer, &mv("e2", "e4"));
+    gui.send(GameToTcp::Resign);
+
+    match peer.receive() {
+        ServerToClient::Resigned { joever, .. } => assert_eq!(joever, Joever::White, "white should win when black resigns"),
+        _ => panic!("expected a resignation"),
+    }
+}
+
+#[test]
+fn server_client_resigns() {
+    let (gui, peer, _) = server_session();
+
+    peer.send(&ClientToServer::Move(mv("e2", "e4")));
+    expect_state(&peer, &mv("e2", "e4"));
+    gui.receive();
+    gui.send(GameToTcp::Move(mv("e7", "e5")));
+    expect_state(&peer, &mv("e7", "e5"));
+    gui.receive();
+    peer.send(&ClientToServer::Resign);
+
+    assert!(matches!(peer.receive(), ServerToClient::Resigned { joever: Joever::Black, .. }), "black should win when white resigns");
+    assert!(matches!(gui.receive(), TcpToGame::Resigned { joever: Joever::Black, .. }), "the server's gui should be told white resigned");
+
+    peer.send(&ClientToServer::Move(mv("d2", "d4")));
+    expect_error(&peer, "The game is already over");
+}
+
+#[test]
+fn server_client_draws() {
+    let (gui, peer, _) = server_session();
+
+    peer.send(&ClientToServer::Draw);
+
+    assert!(matches!(gui.receive(), TcpToGame::DrawOffered), "the server's gui should be offered a draw");
+    gui.send(GameToTcp::Draw);
+
+    assert!(matches!(peer.receive(), ServerToClient::Draw { .. }), "the server should end the game as a draw");
+    assert!(matches!(gui.receive(), TcpToGame::Draw { .. }), "the server's gui should be told about the draw");
+}
+
+#[test]
+fn server_declines_draw() {
+    let (gui, peer, _) = server_session();
+
+    // the decline is an extension, so the client has to have draw offers
+    peer.send(&Extension::Features(extension::supported()));
+    match gui.receive() {
+        TcpToGame::Features(features) => assert!(extension::supports(&features, extension::DRAW_OFFERS), "both sides should have draw offers"),
+        _ => panic!("the server's gui should get the common features"),
+    }
+
+    peer.send(&ClientToServer::Draw);
+    assert!(matches!(gui.receive(), TcpToGame::DrawOffered), "the server's gui should be offered a draw");
+    gui.send(GameToTcp::DeclineDraw);
+
+    assert!(matches!(peer.receive(), Extension::DrawDeclined), "the server should decline the draw");
+
+    // and the game goes on
+    peer.send(&ClientToServer::Move(mv("e2", "e4")));
+    expect_state(&peer, &mv("e2", "e4"));
+}
+
+#[test]
+fn server_malformed_message() {
+    let (_gui, peer, _) = server_session();
+
+    peer.send(&serde_json::json!({ "Castle": 1 }));
+    expect_error(&peer, "isn't part of the protocol");
+
+    // and the game goes on
+    peer.send(&ClientToServer::Move(mv("e2", "e4")));
+    expect_state(&peer, &mv("e2", "e4"));
+}
+
+#[test]
+fn server_client_leaves() {
+    let (gui, peer, _) = server_session();
+
+    peer.send(&ClientToServer::Move(mv("e2", "e4")));
+    expect_state(&peer, &mv("e2", "e4"));
+    drop(peer);
+
+    assert_eq!(gui.receive_disconnect(), "Your opponent left");
+    gui.join();
+}
+
+#[test]
+fn server_broken_json() {
+    let (gui, peer, _) = server_session();
+
+    peer.send_raw(b"{\"Move\": ]");
+
+    let message = gui.receive_disconnect();
+    assert!(message.contains("isn't JSON"), "the gui should hear why the connection was closed, got \"{}\"", message);
+    gui.join();
+}
+
+#[test]
+fn server_rematch_after_resignation() {
+    let (gui, peer, _) = server_session();
+
+    peer.send(&ClientToServer::Resign);
+    peer.receive::<ServerToClient>();
+    assert!(matches!(gui.receive(), TcpToGame::Resigned { .. }), "the server's gui should be told white resigned");
+
+    peer.send(&Extension::Rematch);
+    assert!(matches!(gui.receive(), TcpToGame::RematchOffered), "the server's gui should be offered a rematch");
+    gui.send(GameToTcp::Rematch);
+
+    assert!(matches!(peer.receive(), Extension::Rematch), "the server should agree to the rematch");
+    let handshake: ServerToClientHandshake = peer.receive();
+    assert_eq!(handshake.joever, Joever::Ongoing);
+
+    assert!(matches!(gui.receive(), TcpToGame::Handshake { server_color: Color::White, .. }), "the server should play white in the rematch");
+}
+
+// skips the states and chat the gui gets on the way
+fn expect_takeback_offer(gui: &Gui) {
+    loop {
+        match gui.receive() {
+            TcpToGame::TakebackOffered => return,
+            TcpToGame::State { .. } | TcpToGame::Chat(_) => {},
+            _ => panic!("the server's gui should be asked for a takeback"),
+        }
+    }
+}
+
+#[test]
+fn server_takeback() {
+    let (gui, peer, handshake) = server_session();
+
+    peer.send(&ClientToServer::Move(mv("e2", "e4")));
+    expect_state(&peer, &mv("e2", "e4"));
+    gui.send(GameToTcp::Move(mv("e7", "e5")));
+    expect_state(&peer, &mv("e7", "e5"));
+
+    // white asks on its own turn, so black's answer goes too
+    peer.send(&Extension::Takeback);
+    expect_takeback_offer(&gui);
+    gui.send(GameToTcp::Takeback);
+
+    assert!(matches!(peer.receive(), Extension::Takeback), "the server should accept the takeback");
+    assert!(matches!(peer.receive(), Extension::TakenBack(2)), "the server should take back two plies");
+    match peer.receive() {
+        ServerToClient::Error { board, .. } => assert_eq!(board, handshake.board, "the board should be the start position again"),
+        _ => panic!("the board should follow the takeback"),
+    }
+
+    // and white moves again
+    peer.send(&ClientToServer::Move(mv("d2", "d4")));
+    expect_state(&peer, &mv("d2", "d4"));
+}
+
+#[test]
+fn server_takeback_without_moves() {
+    let (gui, peer, _) = server_session();
+
+    peer.send(&Extension::Takeback);
+    expect_takeback_offer(&gui);
+    gui.send(GameToTcp::Takeback);
+
+    assert!(matches!(peer.receive(), Extension::Takeback), "the server should accept the takeback");
+    expect_error(&peer, "There is no move to take back");
+}
+
+// a client that plays white against the mock peer, which runs a real backend to answer it and has
+// the same extensions
+fn client_session() -> (Gui, MockPeer, erikfran_chess_utils::Game) {
+    let mut features = erikfran_chess_utils::Game::new().features();
+    features.append(&mut extension::features());
+
+    let (gui, peer, game) = client_session_with(features);
+
+    match peer.receive() {
+        Extension::Features(features) => assert!(extension::supports(&features, extension::DRAW_OFFERS), "the client should answer with its extensions"),
+        _ => panic!("the client should answer the handshake with its features"),
+    }
+
+    (gui, peer, game)
+}
+
+// the same with the features the mock advertises in its handshake, after the client's gui got it
+fn client_session_with(features: Vec<Features>) -> (Gui, MockPeer, erikfran_chess_utils::Game) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let (tcp_sender, tcp_receiver) = mpsc::channel();
+    let (game_sender, game_receiver) = mpsc::channel();
+
+    let thread = thread::spawn(move || client::connect(tcp_sender, game_receiver, Color::Black, address));
+
+    let gui = Gui { sender: game_sender, receiver: tcp_receiver, thread };
+    let peer = MockPeer::accept(&listener);
+
+    let handshake: ClientToServerHandshake = peer.receive();
+    assert_eq!(handshake.server_color, Color::Black, "the client should ask the server to play black");
+
+    let mut game = erikfran_chess_utils::Game::new();
+    peer.send(&ServerToClientHandshake {
+        features,
+        board: game.board(),
+        moves: game.possible_moves(),
+        joever: Joever::Ongoing,
+    });
+
+    assert!(matches!(gui.receive(), TcpToGame::Handshake { .. }), "the client's gui didn't get the handshake");
+
+    (gui, peer, game)
+}
+
+// plays the move on the mock's backend and sends the state like a server would
+fn send_state(peer: &MockPeer, game: &mut impl UniversalGame, move_made: Move) {
+    game.try_move(move_made).unwrap();
+
+    peer.send(&ServerToClient::State {
+        board: game.board(),
+        moves: game.possible_moves(),
+        joever: game.joever(),
+        move_made,
+    });
+}
+
+// the client plays e2e4 and the mock answers with the state
+fn client_opens(gui: &Gui, peer: &MockPeer, game: &mut erikfran_chess_utils::Game) {
+    gui.send(GameToTcp::Move(mv("e2", "e4")));
+    peer.receive::<ClientToServer>();
+    send_state(peer, game, mv("e2", "e4"));
+    gui.receive();
+}
+
+#[test]
+fn client_handshake() {
+    client_session();
+}
+
+// a server with extensions of its own can't read ours, so the client doesn't send them
+#[test]
+fn client_foreign_extensions() {
+    let (gui, peer, _) = client_session_with(vec![Features::PossibleMoveGeneration, Features::Other("clocks".to_string())]);
+
+    gui.send(GameToTcp::Move(mv("e2", "e4")));
+    match peer.receive() {
+        ClientToServer::Move(m) => assert_eq!(m, mv("e2", "e4"), "the move should be the first thing the client sends"),
+        _ => panic!("the move should be the first thing the client sends"),
+    }
+}
+
+#[test]
+fn client_legal_move() {
+    let (gui, peer, mut game) = client_session();
+
+    gui.send(GameToTcp::Move(mv("e2", "e4")));
+    match peer.receive() {
+        ClientToServer::Move(m) => assert_eq!(m, mv("e2", "e4")),
+        _ => panic!("the client should send e2e4"),
+    }
+    send_state(&peer, &mut game, mv("e2", "e4"));
+
+    assert!(matches!(gui.receive(), TcpToGame::State { turn: Color::Black, .. }), "the client's gui should get the state with black to move");
+}
+
+#[test]
+fn client_opponent_move() {
+    let (gui, peer, mut game) = client_session();
+
+    client_opens(&gui, &peer, &mut game);
+    send_state(&peer, &mut game, mv("e7", "e5"));
+
+    match gui.receive() {
+        TcpToGame::State { turn, move_made, .. } => {
+            assert_eq!(turn, Color::White);
+            assert_eq!(move_made, mv("e7", "e5"));
+        },
+        _ => panic!("the client's gui should get e7e5"),
+    }
+}
+
+#[test]
+fn client_malformed_message() {
+    let (gui, peer, mut game) = client_session();
+
+    peer.send(&serde_json::json!({ "Castle": 1 }));
+    match gui.receive() {
+        TcpToGame::Error { message } => assert!(message.contains("isn't part of the protocol"), "unexpected error \"{}\"", message),
+        _ => panic!("the client's gui should get an error"),
+    }
+
+    // and the game goes on
+    send_state(&peer, &mut game, mv("e2", "e4"));
+    assert!(matches!(gui.receive(), TcpToGame::State { turn: Color::Black, .. }), "the client's gui should get the state after the error");
+}
+
+#[test]
+fn client_illegal_move() {
+    let (gui, peer, mut game) = client_session();
+
+    gui.send(GameToTcp::Move(mv("e2", "e5")));
+    peer.receive::<ClientToServer>();
+    peer.send(&ServerToClient::Error {
+        board: game.board(),
+        moves: game.possible_moves(),
+        joever: Joever::Ongoing,
+        message: "Pawns don't move like that".to_string(),
+    });
+
+    match gui.receive() {
+        TcpToGame::Resync { turn, message, .. } => {
+            assert_eq!(turn, Color::White, "white should still be to move");
+            assert_eq!(message, "Pawns don't move like that");
+        },
+        _ => panic!("the client's gui should resync"),
+    }
+}
+
+#[test]
+fn client_desync() {
+    let (gui, peer, mut game) = client_session();
+
+    gui.send(GameToTcp::Move(mv("e2", "e4")));
+    peer.receive::<ClientToServer>();
+
+    // the board says d2d4 was played even though the move says e2e4
+    let mut other = erikfran_chess_utils::Game::new();
+    other.try_move(mv("d2", "d4")).unwrap();
+    game.try_move(mv("e2", "e4")).unwrap();
+
+    peer.send(&ServerToClient::State {
+        board: other.board(),
+        moves: other.possible_moves(),
+        joever: Joever::Ongoing,
+        move_made: mv("e2", "e4"),
+    });
+
+    assert!(matches!(gui.receive(), TcpToGame::Desync { .. }), "the client's gui should be told about the desync");
+}
+
+#[test]
+fn client_resigns() {
+    let (gui, peer, game) = client_session();
+
+    gui.send(GameToTcp::Resign);
+    assert!(matches!(peer.receive(), ClientToServer::Resign), "the client should send the resignation");
+    peer.send(&ServerToClient::Resigned { board: game.board(), joever: Joever::Black });
+
+    assert!(matches!(gui.receive(), TcpToGame::Resigned { joever: Joever::Black, .. }), "the client's gui should end the game with black winning");
+}
+
+#[test]
+fn client_draws() {
+    let (gui, peer, game) = client_session();
+
+    gui.send(GameToTcp::Draw);
+    assert!(matches!(peer.receive(), ClientToServer::Draw), "the client should offer the draw");
+    peer.send(&ServerToClient::Draw { board: game.board(), moves: vec![] });
+
+    assert!(matches!(gui.receive(), TcpToGame::Draw { .. }), "the client's gui should end the game as a draw");
+}
+
+#[test]
+fn client_opponent_resigns() {
+    let (gui, peer, mut game) = client_session();
+
+    client_opens(&gui, &peer, &mut game);
+
+    // on the server's turn
+    peer.send(&ServerToClient::Resigned { board: game.board(), joever: Joever::White });
+
+    assert!(matches!(gui.receive(), TcpToGame::Resigned { joever: Joever::White, .. }), "the client's gui should end the game with white winning");
+}
+
+#[test]
+fn client_opponent_draws() {
+    let (gui, peer, mut game) = client_session();
+
+    client_opens(&gui, &peer, &mut game);
+    peer.send(&ServerToClient::Draw { board: game.board(), moves: vec![] });
+
+    assert!(matches!(gui.receive(), TcpToGame::Draw { .. }), "the client's gui should end the game as a draw");
+}
+
+#[test]
+fn client_accepts_draw() {
+    let (gui, peer, game) = client_session();
+
+    peer.send(&Extension::DrawOffer);
+    assert!(matches!(gui.receive(), TcpToGame::DrawOffered), "the client's gui should be offered a draw");
+    gui.send(GameToTcp::Draw);
+
+    assert!(matches!(peer.receive(), ClientToServer::Draw), "the client should accept the draw");
+    peer.send(&ServerToClient::Draw { board: game.board(), moves: vec![] });
+
+    assert!(matches!(gui.receive(), TcpToGame::Draw { .. }), "the client's gui should end the game as a draw");
+}
+
+#[test]
+fn client_declines_draw() {
+    let (gui, peer, _) = client_session();
+
+    peer.send(&Extension::DrawOffer);
+    assert!(matches!(gui.receive(), TcpToGame::DrawOffered), "the client's gui should be offered a draw");
+    gui.send(GameToTcp::DeclineDraw);
+
+    assert!(matches!(peer.receive(), Extension::DrawDeclined), "the client should decline the draw");
+}
+
+#[test]
+fn client_server_leaves() {
+    let (gui, peer, mut game) = client_session();
+
+    client_opens(&gui, &peer, &mut game);
+    drop(peer);
+
+    assert_eq!(gui.receive_disconnect(), "Your opponent left");
+    gui.join();
+}
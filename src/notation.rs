@@ -0,0 +1,447 @@
+use chess_network_protocol::*;
+
+use crate::{piece_color, cords_to_square, cord_to_file, oposite_color, board_utils};
+
+// parses a move typed by the player, either SAN ("e4", "Nf3", "exd5", "O-O", "e8=Q")
+// or coordinate notation ("e2e4", "e7e8q", "e2-e4") and resolves it against the possible moves
+pub fn parse_move(input: &str, board: &[[Piece; 8]; 8], moves: &[Move], turn: &Color) -> Result<Move, String> {
+    let input = input.trim().trim_end_matches(|c| c == '+' || c == '#' || c == '!' || c == '?');
+
+    if input.is_empty() {
+        return Err("No move entered".to_string());
+    }
+
+    if let Some(mv) = parse_castling(input, moves, turn)? {
+        return Ok(mv);
+    }
+
+    if let Some(mv) = parse_coordinate(input, board, moves, turn)? {
+        return Ok(mv);
+    }
+
+    parse_san(input, board, moves, turn)
+}
+
+fn parse_castling(input: &str, moves: &[Move], turn: &Color) -> Result<Option<Move>, String> {
+    let end_x = match input.replace('0', "O").as_str() {
+        "O-O" => 6,
+        "O-O-O" => 2,
+        _ => return Ok(None),
+    };
+
+    let rank = match turn {
+        Color::White => 0,
+        Color::Black => 7,
+    };
+
+    let mv = Move {
+        start_x: 4,
+        start_y: rank,
+        end_x,
+        end_y: rank,
+        promotion: Piece::None,
+    };
+
+    if !moves.is_empty() && !moves.contains(&mv) {
+        return Err(format!("{} is not a legal move right now", input));
+    }
+
+    Ok(Some(mv))
+}
+
+fn parse_coordinate(input: &str, board: &[[Piece; 8]; 8], moves: &[Move], turn: &Color) -> Result<Option<Move>, String> {
+    let chars: Vec<char> = input.chars().filter(|c| *c != '-' && *c != '=').collect();
+
+    if chars.len() != 4 && chars.len() != 5 {
+        return Ok(None);
+    }
+
+    let (start_x, start_y, end_x, end_y) = match (
+        file_from_char(chars[0]),
+        rank_from_char(chars[1]),
+        file_from_char(chars[2]),
+        rank_from_char(chars[3])
+    ) {
+        (Some(a), Some(b), Some(c), Some(d)) => (a, b, c, d),
+        _ => return Ok(None),
+    };
+
+    let promotion = match chars.get(4) {
+        Some(c) => match piece_from_char(c.to_ascii_uppercase(), turn) {
+            Some(p) if !is_pawn(&p) && !is_king(&p) => p,
+            _ => return Err(format!("{} is not a valid promotion piece", c)),
+        },
+        None => Piece::None,
+    };
+
+    if piece_color(&board[start_y][start_x]) != Some(turn.clone()) {
+        return Err(format!("You have no piece on {}", cords_to_square(start_x as f32, start_y as f32)));
+    }
+
+    let mv = Move { start_x, start_y, end_x, end_y, promotion };
+
+    // backends without move generation send no moves, so let the server decide
+    if moves.is_empty() {
+        return Ok(Some(mv));
+    }
+
+    if moves.contains(&mv) {
+        return Ok(Some(mv));
+    }
+
+    let same_squares: Vec<&Move> = moves.iter()
+        .filter(|m| m.start_x == start_x && m.start_y == start_y && m.end_x == end_x && m.end_y == end_y)
+        .collect();
+
+    // some backends list a promotion once with Piece::None, the piece that was asked for is sent
+    if promotion != Piece::None && same_squares.iter().any(|m| m.promotion == Piece::None) {
+        return Ok(Some(mv));
+    }
+
+    let promotions = same_squares.len();
+
+    if promotion == Piece::None && promotions > 1 {
+        return Err(format!("{} needs a promotion piece, for example {}q", input, input));
+    }
+
+    Err(format!("{} is not a legal move right now", input))
+}
+
+fn parse_san(input: &str, board: &[[Piece; 8]; 8], moves: &[Move], turn: &Color) -> Result<Move, String> {
+    let mut chars: Vec<char> = input.chars().filter(|c| *c != 'x' && *c != ':' && *c != '-').collect();
+
+    let piece = match chars.first() {
+        Some(c) if c.is_ascii_uppercase() => {
+            let p = piece_from_char(*c, turn).ok_or(format!("{} is not a piece", c))?;
+            chars.remove(0);
+            p
+        },
+        _ => pawn(turn),
+    };
+
+    // promotion is written as e8=Q or e8Q, the '=' is optional
+    let mut promotion = Piece::None;
+    if let Some(c) = chars.last().copied().filter(|c| c.is_ascii_uppercase()) {
+        promotion = match piece_from_char(c, turn) {
+            Some(p) if !is_pawn(&p) && !is_king(&p) => p,
+            _ => return Err(format!("{} is not a valid promotion piece", c)),
+        };
+        chars.pop();
+    }
+    if chars.last() == Some(&'=') {
+        chars.pop();
+    }
+
+    if chars.len() < 2 {
+        return Err(format!("Could not understand the move {}", input));
+    }
+
+    let target = chars.split_off(chars.len() - 2);
+    let (end_x, end_y) = match (file_from_char(target[0]), rank_from_char(target[1])) {
+        (Some(x), Some(y)) => (x, y),
+        _ => return Err(format!("Could not understand the move {}", input)),
+    };
+
+    // whatever is left is the disambiguation, a file, a rank or both
+    let mut from_x = None;
+    let mut from_y = None;
+    for c in chars {
+        if let Some(x) = file_from_char(c) {
+            from_x = Some(x);
+        }
+        else if let Some(y) = rank_from_char(c) {
+            from_y = Some(y);
+        }
+        else {
+            return Err(format!("Could not understand the move {}", input));
+        }
+    }
+
+    if moves.is_empty() {
+        return Err("The server does not send possible moves, use coordinate notation like e2e4".to_string());
+    }
+
+    let candidates: Vec<&Move> = moves.iter()
+        .filter(|m| m.end_x == end_x && m.end_y == end_y)
+        .filter(|m| board[m.start_y][m.start_x] == piece)
+        .filter(|m| from_x.map_or(true, |x| m.start_x == x))
+        .filter(|m| from_y.map_or(true, |y| m.start_y == y))
+        .filter(|m| promotion == Piece::None || m.promotion == promotion || m.promotion == Piece::None)
+        .collect();
+
+    let mut starts: Vec<(usize, usize)> = candidates.iter().map(|m| (m.start_x, m.start_y)).collect();
+    starts.sort();
+    starts.dedup();
+
+    match starts.len() {
+        0 => Err(format!("{} is not a legal move right now", input)),
+        1 => {
+            if candidates.len() > 1 {
+                return Err(format!("{} needs a promotion piece, for example {}=Q", input, input));
+            }
+            if promotion != Piece::None {
+                return Ok(Move { promotion, ..*candidates[0] });
+            }
+            Ok(*candidates[0])
+        },
+        _ => Err(format!("{} is ambiguous, it could be played from {}",
+            input,
+            starts.iter()
+                .map(|(x, y)| cords_to_square(*x as f32, *y as f32))
+                .collect::<Vec<String>>()
+                .join(" or ")
+        )),
+    }
+}
+
+// writes a move in SAN from the boards before and after it, ambiguity is resolved with pseudo legal
+// moves since the history doesn't keep the possible moves of old positions
+pub fn to_san(before: &[[Piece; 8]; 8], after: &[[Piece; 8]; 8], mv: &Move) -> String {
+    let piece = before[mv.start_y][mv.start_x];
+    let color = piece_color(&piece).unwrap_or(Color::White);
+    let kind = board_utils::classify_move(before, mv);
+    let target = cords_to_square(mv.end_x as f32, mv.end_y as f32);
+
+    let mut san = match kind {
+        board_utils::MoveKind::Castle { rook_from: (0, _), .. } => "O-O-O".to_string(),
+        board_utils::MoveKind::Castle { .. } => "O-O".to_string(),
+        _ if is_pawn(&piece) => {
+            let mut san = String::new();
+            if kind.is_capture() {
+                san += &cord_to_file(mv.start_x as f32);
+                san += "x";
+            }
+            san += &target;
+            if let board_utils::MoveKind::Promotion { .. } = kind {
+                // backends that don't send the promotion piece promote to a queen
+                let promoted = after[mv.end_y][mv.end_x];
+                san += &format!("={}", piece_letter(if promoted == Piece::None { &mv.promotion } else { &promoted }));
+            }
+            san
+        },
+        _ => {
+            let others: Vec<Move> = board_utils::pseudo_legal_moves(before, &color).into_iter()
+                .filter(|m| m.end_x == mv.end_x && m.end_y == mv.end_y)
+                .filter(|m| (m.start_x, m.start_y) != (mv.start_x, mv.start_y))
+                .filter(|m| before[m.start_y][m.start_x] == piece)
+                .collect();
+
+            let mut san = piece_letter(&piece).to_string();
+            if !others.is_empty() {
+                if others.iter().all(|m| m.start_x != mv.start_x) {
+                    san += &cord_to_file(mv.start_x as f32);
+                }
+                else if others.iter().all(|m| m.start_y != mv.start_y) {
+                    san += &(mv.start_y + 1).to_string();
+                }
+                else {
+                    san += &cords_to_square(mv.start_x as f32, mv.start_y as f32);
+                }
+            }
+            if kind.is_capture() {
+                san += "x";
+            }
+            san + &target
+        },
+    };
+
+    if board_utils::in_check(after, &oposite_color(&color)) {
+        san += "+";
+    }
+
+    san
+}
+
+fn piece_letter(piece: &Piece) -> &'static str {
+    match piece {
+        Piece::WhiteKnight | Piece::BlackKnight => "N",
+        Piece::WhiteBishop | Piece::BlackBishop => "B",
+        Piece::WhiteRook | Piece::BlackRook => "R",
+        Piece::WhiteQueen | Piece::BlackQueen => "Q",
+        Piece::WhiteKing | Piece::BlackKing => "K",
+        Piece::WhitePawn | Piece::BlackPawn | Piece::None => "",
+    }
+}
+
+fn file_from_char(c: char) -> Option<usize> {
+    match c {
+        'a'..='h' => Some(c as usize - 'a' as usize),
+        _ => None,
+    }
+}
+
+fn rank_from_char(c: char) -> Option<usize> {
+    match c {
+        '1'..='8' => Some(c as usize - '1' as usize),
+        _ => None,
+    }
+}
+
+fn pawn(color: &Color) -> Piece {
+    match color {
+        Color::White => Piece::WhitePawn,
+        Color::Black => Piece::BlackPawn,
+    }
+}
+
+fn is_pawn(piece: &Piece) -> bool {
+    *piece == Piece::WhitePawn || *piece == Piece::BlackPawn
+}
+
+fn is_king(piece: &Piece) -> bool {
+    *piece == Piece::WhiteKing || *piece == Piece::BlackKing
+}
+
+fn piece_from_char(c: char, color: &Color) -> Option<Piece> {
+    match color {
+        Color::White => match c {
+            'P' => Some(Piece::WhitePawn),
+            'N' => Some(Piece::WhiteKnight),
+            'B' => Some(Piece::WhiteBishop),
+            'R' => Some(Piece::WhiteRook),
+            'Q' => Some(Piece::WhiteQueen),
+            'K' => Some(Piece::WhiteKing),
+            _ => None,
+        },
+        Color::Black => match c {
+            'P' => Some(Piece::BlackPawn),
+            'N' => Some(Piece::BlackKnight),
+            'B' => Some(Piece::BlackBishop),
+            'R' => Some(Piece::BlackRook),
+            'Q' => Some(Piece::BlackQueen),
+            'K' => Some(Piece::BlackKing),
+            _ => None,
+        },
+    }
+}
+
+// coordinate notation, "e2e4" or "e7e8q"
+pub fn to_coordinate(mv: &Move) -> String {
+    let mut text = cords_to_square(mv.start_x as f32, mv.start_y as f32) + &cords_to_square(mv.end_x as f32, mv.end_y as f32);
+
+    if mv.promotion != Piece::None {
+        text += &piece_letter(&mv.promotion).to_lowercase();
+    }
+
+    text
+}
+
+// the FEN after playing the moves from a start position with white to move. a castling right is
+// lost once anything moves from or to the king's or the rook's square
+pub fn to_fen(start: &[[Piece; 8]; 8], moves: &[Move]) -> String {
+    let mut board = *start;
+    // K, Q, k, q
+    let mut castling = [
+        start[0][4] == Piece::WhiteKing && start[0][7] == Piece::WhiteRook,
+        start[0][4] == Piece::WhiteKing && start[0][0] == Piece::WhiteRook,
+        start[7][4] == Piece::BlackKing && start[7][7] == Piece::BlackRook,
+        start[7][4] == Piece::BlackKing && start[7][0] == Piece::BlackRook,
+    ];
+    let mut en_passant = "-".to_string();
+    let mut halfmoves = 0;
+
+    for mv in moves {
+        let piece = board[mv.start_y][mv.start_x];
+
+        for square in [(mv.start_x, mv.start_y), (mv.end_x, mv.end_y)] {
+            match square {
+                (4, 0) => castling[0..2].fill(false),
+                (4, 7) => castling[2..4].fill(false),
+                (7, 0) => castling[0] = false,
+                (0, 0) => castling[1] = false,
+                (7, 7) => castling[2] = false,
+                (0, 7) => castling[3] = false,
+                _ => {},
+            }
+        }
+
+        halfmoves = if is_pawn(&piece) || board_utils::classify_move(&board, mv).is_capture() { 0 } else { halfmoves + 1 };
+
+        en_passant = if is_pawn(&piece) && mv.start_y.abs_diff(mv.end_y) == 2 {
+            cords_to_square(mv.start_x as f32, ((mv.start_y + mv.end_y) / 2) as f32)
+        }
+        else {
+            "-".to_string()
+        };
+
+        board = board_utils::apply_move(&board, mv);
+    }
+
+    let rights: String = ["K", "Q", "k", "q"].iter()
+        .zip(castling)
+        .filter(|(_, right)| *right)
+        .map(|(letter, _)| *letter)
+        .collect();
+
+    format!("{} {} {} {} {} {}",
+        placement(&board),
+        if moves.len() % 2 == 0 { "w" } else { "b" },
+        if rights.is_empty() { "-" } else { &rights },
+        en_passant,
+        halfmoves,
+        moves.len() / 2 + 1,
+    )
+}
+
+// the board part of a FEN
+pub fn placement(board: &[[Piece; 8]; 8]) -> String {
+    let mut ranks = vec![];
+
+    for y in (0..8).rev() {
+        let mut rank = String::new();
+        let mut empty = 0;
+
+        for x in 0..8 {
+            let piece = board[y][x];
+
+            if piece == Piece::None {
+                empty += 1;
+                continue;
+            }
+            if empty > 0 {
+                rank += &empty.to_string();
+                empty = 0;
+            }
+
+            let letter = if is_pawn(&piece) { "P" } else { piece_letter(&piece) };
+            rank += &match piece_color(&piece) {
+                Some(Color::Black) => letter.to_lowercase(),
+                _ => letter.to_string(),
+            };
+        }
+
+        if empty > 0 {
+            rank += &empty.to_string();
+        }
+        ranks.push(rank);
+    }
+
+    ranks.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use chess_network_protocol::*;
+
+    use super::parse_move;
+
+    // a white pawn on e7 and the kings, with the promotion listed once the way some backends do it
+    fn promotion_position() -> ([[Piece; 8]; 8], Vec<Move>) {
+        let mut board = [[Piece::None; 8]; 8];
+        board[6][4] = Piece::WhitePawn;
+        board[0][0] = Piece::WhiteKing;
+        board[7][0] = Piece::BlackKing;
+
+        let moves = vec![Move { start_x: 4, start_y: 6, end_x: 4, end_y: 7, promotion: Piece::None }];
+        (board, moves)
+    }
+
+    #[test]
+    fn promotion_listed_without_piece() {
+        let (board, moves) = promotion_position();
+        let expected = Move { start_x: 4, start_y: 6, end_x: 4, end_y: 7, promotion: Piece::WhiteKnight };
+
+        assert_eq!(parse_move("e7e8n", &board, &moves, &Color::White), Ok(expected));
+        assert_eq!(parse_move("e8=N", &board, &moves, &Color::White), Ok(expected));
+    }
+}
@@ -0,0 +1,197 @@
+use chess_network_protocol::*;
+
+use std::process;
+use std::time::Instant;
+
+use crate::server::UniversalGame;
+use crate::{notation, extension, backends};
+
+// cargo run -- perft [depth] [backends...]
+// counts the leaf nodes of the move tree through possible_moves and compares them with the known
+// counts of reference positions. UniversalGame can't load a FEN so every position is reached by
+// playing moves from the start, then the tree is walked with try_move and undo_last_move. exits
+// with 1 if any count is wrong
+
+const DEPTH: usize = 3;
+
+struct Position {
+    name: &'static str,
+    fen: &'static str,
+    moves: &'static str,
+    // nodes at depth 1, 2, 3 and so on
+    counts: &'static [u64],
+}
+
+enum Outcome {
+    Passed,
+    Failed,
+    // the backend doesn't generate moves, so there is nothing to count
+    Skipped,
+}
+
+const POSITIONS: [Position; 4] = [
+    Position {
+        name: "initial position",
+        fen: "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        moves: "",
+        counts: &[20, 400, 8902, 197281, 4865609],
+    },
+    // castling both ways, en passant and promotions a few plies in
+    Position {
+        name: "kiwipete",
+        fen: "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        moves: "g1f3 b7b5 f3g1 b5b4 g1f3 h7h5 f3g1 h5h4 g1f3 h4h3 f3g1 e7e6 d2d4 g7g6 d4d5 f8g7 e2e4 c8a6 \
+                b1c3 g8f6 c1d2 d8e7 g1f3 b8c6 f3e5 c6a5 d1f3 a5c4 f1e2 c4b6",
+        counts: &[48, 2039, 97862, 4085603],
+    },
+    // a promotion right away, with and without a capture
+    Position {
+        name: "promotion (perft position 5)",
+        fen: "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        moves: "e2e4 e7e5 d2d4 g8f6 d4e5 f6e4 f1c4 c7c6 e5e6 f8e7 e6d7 e8f8 g1e2 e4f2",
+        counts: &[44, 1486, 62379, 2103487],
+    },
+    // exd6 en passant is possible right away
+    Position {
+        name: "en passant",
+        fen: "rnbqkbnr/1pp1pppp/p7/3pP3/8/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 3",
+        moves: "e2e4 a7a6 e4e5 d7d5",
+        counts: &[31, 781, 24166, 630536],
+    },
+];
+
+pub fn run(args: &[String]) {
+    let depth = args.first().and_then(|a| a.parse().ok()).unwrap_or(DEPTH);
+
+    let names: Vec<&str> = match args.get(1..) {
+        Some(names) if !names.is_empty() => names.iter().map(|n| n.as_str()).collect(),
+        _ => backends::names(),
+    };
+
+    let mut passed = 0;
+    let mut failed = 0;
+    let mut skipped = 0;
+
+    for name in &names {
+        println!("{}", name);
+
+        let outcome = match backends::find(name) {
+            Some(backend) => check((backend.new)().as_mut(), depth),
+            None => {
+                println!("  unknown backend {}, expected one of {}", name, backends::names().join(", "));
+                Outcome::Failed
+            },
+        };
+
+        match outcome {
+            Outcome::Passed => passed += 1,
+            Outcome::Failed => failed += 1,
+            Outcome::Skipped => skipped += 1,
+        }
+    }
+
+    println!("{} passed, {} failed, {} skipped", passed, failed, skipped);
+
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+// every position up to the depth, or as deep as the known counts go
+fn check(game: &mut dyn UniversalGame, depth: usize) -> Outcome {
+    if !extension::has(&game.features(), &Features::PossibleMoveGeneration) {
+        println!("  skipped, the backend doesn't generate moves");
+        return Outcome::Skipped;
+    }
+
+    let mut outcome = Outcome::Passed;
+
+    for position in &POSITIONS {
+        let path = match parse_moves(position.moves) {
+            Ok(path) => path,
+            Err(e) => {
+                println!("  FAILED  {}: {}", position.name, e);
+                outcome = Outcome::Failed;
+                continue;
+            },
+        };
+
+        // the tree is walked from here, restore stops just before the move it refused
+        if let Err(e) = game.restore(&path) {
+            println!("  FAILED  {}: {}", position.name, refused(game, &path[game.history().len()], &e));
+            outcome = Outcome::Failed;
+            continue;
+        }
+
+        for (i, expected) in position.counts.iter().take(depth).enumerate() {
+            let started = Instant::now();
+
+            match perft(game, i + 1) {
+                Ok(nodes) if nodes == *expected => {
+                    println!("  ok      {} depth {}: {} nodes in {:.2}s", position.name, i + 1, nodes, started.elapsed().as_secs_f64());
+                },
+                Ok(nodes) => {
+                    println!("  FAILED  {} depth {}: {} nodes, expected {}", position.name, i + 1, nodes, expected);
+                    println!("          {}", position.fen);
+                    outcome = Outcome::Failed;
+                    // the deeper counts can only be wrong too
+                    break;
+                },
+                Err(e) => {
+                    println!("  FAILED  {} depth {}: {}", position.name, i + 1, e);
+                    outcome = Outcome::Failed;
+                    break;
+                },
+            }
+        }
+    }
+
+    outcome
+}
+
+// the leaf nodes below the current position, the moves of the last ply are counted without being
+// played. the game is back on the same position when it returns Ok
+fn perft(game: &mut dyn UniversalGame, depth: usize) -> Result<u64, String> {
+    let moves = game.possible_moves();
+
+    if depth <= 1 {
+        return Ok(moves.len() as u64);
+    }
+
+    let mut nodes = 0;
+
+    for mv in moves {
+        if let Err(e) = game.try_move(mv) {
+            return Err(refused(game, &mv, &e));
+        }
+
+        nodes += perft(game, depth - 1)?;
+        game.undo_last_move()?;
+    }
+
+    Ok(nodes)
+}
+
+fn refused(game: &dyn UniversalGame, mv: &Move, e: &str) -> String {
+    let moves: Vec<String> = game.history().iter().map(notation::to_coordinate).collect();
+    format!("the backend refuses {} after {}: {}", notation::to_coordinate(mv), moves.join(" "), e)
+}
+
+// "e2e4 e7e5", the reference moves have no promotions
+fn parse_moves(text: &str) -> Result<Vec<Move>, String> {
+    text.split_whitespace()
+        .map(|mv| {
+            let squares: Vec<usize> = mv.bytes()
+                .enumerate()
+                .map(|(i, b)| b.wrapping_sub(if i % 2 == 0 { b'a' } else { b'1' }) as usize)
+                .collect();
+
+            match squares[..] {
+                [start_x, start_y, end_x, end_y] if squares.iter().all(|s| *s < 8) => {
+                    Ok(Move { start_x, start_y, end_x, end_y, promotion: Piece::None })
+                },
+                _ => Err(format!("{} isn't a move", mv)),
+            }
+        })
+        .collect()
+}
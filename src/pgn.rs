@@ -0,0 +1,62 @@
+use chess_network_protocol::*;
+
+use crate::{HistoryEntry, ChatMessage, annotations, notation};
+
+pub fn result(joever: &Joever) -> &'static str {
+    match joever {
+        Joever::White => "1-0",
+        Joever::Black => "0-1",
+        Joever::Draw => "1/2-1/2",
+        Joever::Ongoing | Joever::Indeterminate => "*",
+    }
+}
+
+// the game as PGN, the annotations drawn on each position and the chat are written as comments after the move that led to it
+pub fn write_pgn(history: &[HistoryEntry], chat: &[ChatMessage], joever: &Joever, with_annotations: bool) -> String {
+    let mut pgn = format!("[Event \"erikfran-chess-gui game\"]\n[Result \"{}\"]\n\n", result(joever));
+    let mut tokens = vec![];
+
+    for (i, entry) in history.iter().enumerate() {
+        if let (Some(mv), Some(previous)) = (entry.last_move, i.checked_sub(1).and_then(|j| history.get(j))) {
+            if i % 2 == 1 {
+                tokens.push(format!("{}.", i / 2 + 1));
+            }
+            tokens.push(notation::to_san(&previous.board, &entry.board, &mv));
+        }
+
+        if with_annotations {
+            if let Some(comment) = annotations::pgn_comment(&entry.annotations) {
+                tokens.push(format!("{{{}}}", comment));
+            }
+        }
+
+        for message in chat.iter().filter(|m| m.ply == i) {
+            let name = match message.color {
+                Color::White => "White",
+                Color::Black => "Black",
+            };
+            // a } would end the comment early
+            tokens.push(format!("{{{}: {}}}", name, message.text.replace('}', ")")));
+        }
+    }
+
+    tokens.push(result(joever).to_string());
+
+    // PGN lines should stay below 80 characters
+    let mut line = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + token.len() + 1 > 79 {
+            pgn += &line;
+            pgn += "\n";
+            line.clear();
+        }
+        if !line.is_empty() {
+            line += " ";
+        }
+        line += &token;
+    }
+    pgn += &line;
+    pgn += "\n";
+
+    pgn
+}
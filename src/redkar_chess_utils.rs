@@ -9,6 +9,7 @@ pub struct Game {
     turn: Color,
     joever: Joever,
     features: Vec<Features>,
+    history: Vec<Move>,
     game: chess::Game,
 }
 
@@ -22,6 +23,7 @@ impl UniversalGame for Game {
             turn: Color::White,
             joever: Joever::Ongoing,
             features: vec![],
+            history: vec![],
             game,
         }
     }
@@ -34,6 +36,7 @@ impl UniversalGame for Game {
 
         self.board = self.game.board.into_network();
         self.turn = self.game.turn.into_network();
+        self.history.push(m);
 
         Ok(())
     }
@@ -57,6 +60,14 @@ impl UniversalGame for Game {
     fn features(&self) -> Vec<Features> {
         self.features.clone()
     }
+
+    fn history(&self) -> &[Move] {
+        &self.history
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
 }
 
 pub fn explain_move_error(e: chess::MoveError) -> String {
@@ -0,0 +1,132 @@
+use chess_network_protocol::*;
+use serde_json::{Value, from_value};
+
+use std::process;
+
+use crate::server::{self, UniversalGame};
+use crate::transcript::{self, Entry, Side};
+use crate::extension::Extension;
+use crate::{backends, oposite_color, cords_to_square};
+
+// cargo run -- replay <transcript> [backend]
+// exits with 1 when the backend disagrees with the transcript
+pub fn run(args: &[String]) {
+    let path = match args.first() {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: replay <transcript> [{}]", backends::names().join("|"));
+            process::exit(1);
+        },
+    };
+
+    let entries = match transcript::read(path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("could not read {}: {}", path, e);
+            process::exit(1);
+        },
+    };
+
+    let name = args.get(1).map(|s| s.as_str()).unwrap_or(backends::BACKENDS[0].name);
+    let result = match backends::find(name) {
+        // with the same draw rules as the server
+        Some(backend) => replay((backend.with_rules)(), &entries),
+        None => {
+            eprintln!("unknown backend {}, expected one of {}", name, backends::names().join(", "));
+            process::exit(1);
+        },
+    };
+
+    match result {
+        Ok(plies) => println!("replayed {} plies, the backend agrees with the transcript", plies),
+        Err(message) => {
+            println!("{}", message);
+            process::exit(1);
+        },
+    }
+}
+
+// plays every move the server accepted on a fresh backend and checks that the boards and results
+// match, and that the moves the server refused are refused again. returns the number of plies
+pub fn replay(mut game: Box<dyn UniversalGame>, entries: &[Entry]) -> Result<usize, String> {
+    let mut plies = 0;
+    let mut games = 0;
+    let mut server_color = None;
+    let mut attempted = None;
+
+    for (i, entry) in entries.iter().enumerate() {
+        let line = i + 1;
+
+        match entry.from {
+            Side::Client => {
+                if let Ok(handshake) = from_value::<ClientToServerHandshake>(entry.message.clone()) {
+                    server_color = Some(handshake.server_color);
+                }
+                else if let Ok(ClientToServer::Move(mv)) = from_value(entry.message.clone()) {
+                    attempted = Some(mv);
+                }
+            },
+            Side::Server => {
+                // every handshake after the first one is a rematch with the colors swapped
+                if is_handshake(&entry.message) {
+                    if games > 0 {
+                        game.reset();
+                        server_color = server_color.map(|c| oposite_color(&c));
+                    }
+                    games += 1;
+                    continue;
+                }
+
+                if let Ok(Extension::TakenBack(taken)) = from_value(entry.message.clone()) {
+                    let mut history = game.snapshot();
+                    history.truncate(history.len().saturating_sub(taken));
+
+                    if let Err(e) = game.restore(&history) {
+                        return Err(format!("line {}: the backend can't take back {} plies: {}", line, taken, e));
+                    }
+                    continue;
+                }
+
+                match from_value::<ServerToClient>(entry.message.clone()) {
+                    Ok(ServerToClient::State { board, joever, move_made, .. }) => {
+                        if let Err(e) = game.try_move(move_made) {
+                            return Err(format!("line {}: the backend refuses {} which the server played: {}", line, move_text(&move_made), e));
+                        }
+                        plies += 1;
+                        attempted = None;
+
+                        if game.board() != board {
+                            return Err(format!("line {}: the board after {} differs from the transcript", line, move_text(&move_made)));
+                        }
+                        if game.joever() != joever {
+                            return Err(format!("line {}: the game state after {} differs from the transcript", line, move_text(&move_made)));
+                        }
+                    },
+                    Ok(ServerToClient::Error { message, .. }) => {
+                        if let Some(mv) = attempted.take() {
+                            let replayed = match &server_color {
+                                Some(color) => server::validate_move(&game, &oposite_color(color), &mv).and_then(|()| game.try_move(mv)),
+                                None => game.try_move(mv),
+                            };
+
+                            if replayed.is_ok() {
+                                return Err(format!("line {}: the server refused {} with \"{}\" but the backend accepts it", line, move_text(&mv), message));
+                            }
+                        }
+                    },
+                    _ => {},
+                }
+            },
+        }
+    }
+
+    Ok(plies)
+}
+
+fn is_handshake(message: &Value) -> bool {
+    from_value::<ServerToClientHandshake>(message.clone()).is_ok()
+}
+
+fn move_text(mv: &Move) -> String {
+    cords_to_square(mv.start_x as f32, mv.start_y as f32) + &cords_to_square(mv.end_x as f32, mv.end_y as f32)
+}
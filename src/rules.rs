@@ -0,0 +1,222 @@
+use chess_network_protocol::*;
+
+use crate::server::UniversalGame;
+use crate::{board_utils, notation, piece_color};
+
+// the draws none of the backends report, checked on the network board after every move so they
+// work the same whichever backend is underneath: stalemate, threefold repetition, the fifty-move
+// rule and positions where neither side can mate
+pub struct Rules<G: UniversalGame> {
+    game: G,
+    start: [[Piece; 8]; 8],
+    // every position so far, as the parts of its FEN that make it the same position
+    positions: Vec<String>,
+    // plies since the last capture or pawn move
+    halfmoves: usize,
+    draw: bool,
+}
+
+impl<G: UniversalGame> UniversalGame for Rules<G> {
+    fn new() -> Self {
+        let game = G::new();
+        let start = game.board();
+
+        Self {
+            positions: vec![position(&start, &start, &[])],
+            game,
+            start,
+            halfmoves: 0,
+            draw: false,
+        }
+    }
+
+    fn try_move(&mut self, m: Move) -> Result<(), String> {
+        if self.draw {
+            return Err("The game is already over".to_string());
+        }
+
+        let before = self.game.board();
+        self.game.try_move(m)?;
+
+        let pawn = matches!(before[m.start_y][m.start_x], Piece::WhitePawn | Piece::BlackPawn);
+        self.halfmoves = if pawn || board_utils::classify_move(&before, &m).is_capture() { 0 } else { self.halfmoves + 1 };
+
+        let current = position(&self.game.board(), &self.start, self.game.history());
+        let repetitions = self.positions.iter().filter(|p| **p == current).count() + 1;
+        self.positions.push(current);
+
+        if self.game.joever() == Joever::Ongoing {
+            self.draw = repetitions >= 3
+                || self.halfmoves >= 100
+                || insufficient_material(&self.game.board())
+                || self.stalemate();
+        }
+
+        Ok(())
+    }
+
+    fn possible_moves(&mut self) -> Vec<Move> {
+        if self.draw {
+            return vec![];
+        }
+
+        self.game.possible_moves()
+    }
+
+    fn board(&self) -> [[Piece; 8]; 8] {
+        self.game.board()
+    }
+
+    fn turn(&self) -> Color {
+        self.game.turn()
+    }
+
+    fn joever(&self) -> Joever {
+        if self.draw {
+            return Joever::Draw;
+        }
+
+        self.game.joever()
+    }
+
+    fn features(&self) -> Vec<Features> {
+        let mut features = self.game.features();
+
+        if !features.iter().any(|f| matches!(f, Features::Stalemate)) {
+            features.push(Features::Stalemate);
+        }
+
+        features
+    }
+
+    fn history(&self) -> &[Move] {
+        self.game.history()
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+impl<G: UniversalGame> Rules<G> {
+    // no legal move without being in check. backends that don't generate moves get the moves from
+    // the board that don't leave the king in check
+    fn stalemate(&mut self) -> bool {
+        let turn = self.game.turn();
+        let board = self.game.board();
+
+        if board_utils::in_check(&board, &turn) {
+            return false;
+        }
+
+        if self.game.features().iter().any(|f| matches!(f, Features::PossibleMoveGeneration)) {
+            return self.game.possible_moves().is_empty();
+        }
+
+        !board_utils::pseudo_legal_moves(&board, &turn)
+            .iter()
+            .any(|mv| !board_utils::in_check(&board_utils::apply_move(&board, mv), &turn))
+    }
+}
+
+// the pieces, the side to move, the castling rights and the en passant square, but only if a pawn
+// can actually take there since that is what makes two positions the same for repetition
+fn position(board: &[[Piece; 8]; 8], start: &[[Piece; 8]; 8], moves: &[Move]) -> String {
+    let fen = notation::to_fen(start, moves);
+    let fields: Vec<&str> = fen.split(' ').collect();
+
+    let en_passant = match moves.last() {
+        Some(last) if fields[3] != "-" => {
+            let taker = match piece_color(&board[last.end_y][last.end_x]) {
+                Some(Color::White) => Piece::BlackPawn,
+                _ => Piece::WhitePawn,
+            };
+            let beside = [last.end_x.checked_sub(1), Some(last.end_x + 1).filter(|x| *x < 8)];
+
+            if beside.iter().flatten().any(|x| board[last.end_y][*x] == taker) { fields[3] } else { "-" }
+        },
+        _ => "-",
+    };
+
+    format!("{} {} {} {}", fields[0], fields[1], fields[2], en_passant)
+}
+
+// no sequence of legal moves can end in mate: kings alone, a single minor piece, or only bishops
+// that all stand on squares of the same color
+pub fn insufficient_material(board: &[[Piece; 8]; 8]) -> bool {
+    let mut minors = vec![];
+
+    for y in 0..8 {
+        for x in 0..8 {
+            match board[y][x] {
+                Piece::None | Piece::WhiteKing | Piece::BlackKing => {},
+                Piece::WhiteKnight | Piece::BlackKnight | Piece::WhiteBishop | Piece::BlackBishop => minors.push((board[y][x], (x + y) % 2)),
+                _ => return false,
+            }
+        }
+    }
+
+    let bishops_only = minors.iter().all(|(piece, _)| matches!(piece, Piece::WhiteBishop | Piece::BlackBishop));
+    let one_square_color = minors.windows(2).all(|pair| pair[0].1 == pair[1].1);
+
+    minors.len() <= 1 || (bishops_only && one_square_color)
+}
+
+#[cfg(test)]
+mod tests {
+    use chess_network_protocol::*;
+
+    use crate::backends;
+    use crate::test_utils::play;
+
+    // sam loyd's ten move stalemate
+    #[test]
+    fn stalemate() {
+        for backend in backends::BACKENDS {
+            let mut game = (backend.with_rules)();
+            play(game.as_mut(), &[
+                ("e2", "e3"), ("a7", "a5"), ("d1", "h5"), ("a8", "a6"), ("h5", "a5"), ("h7", "h5"), ("h2", "h4"), ("a6", "h6"),
+                ("a5", "c7"), ("f7", "f6"), ("c7", "d7"), ("e8", "f7"), ("d7", "b7"), ("d8", "d3"), ("b7", "b8"), ("d3", "h7"),
+                ("b8", "c8"), ("f7", "g6"),
+            ]).expect(backend.name);
+            assert_eq!(game.joever(), Joever::Ongoing, "{}: black still has moves", backend.name);
+
+            play(game.as_mut(), &[("c8", "e6")]).expect(backend.name);
+            assert_eq!(game.joever(), Joever::Draw, "{}: black has no moves and isn't in check", backend.name);
+        }
+    }
+
+    #[test]
+    fn threefold_repetition() {
+        for backend in backends::BACKENDS {
+            let mut game = (backend.with_rules)();
+            let shuffle = [("g1", "f3"), ("g8", "f6"), ("f3", "g1"), ("f6", "g8")];
+
+            play(game.as_mut(), &shuffle).expect(backend.name);
+            play(game.as_mut(), &shuffle[..3]).expect(backend.name);
+            assert_eq!(game.joever(), Joever::Ongoing, "{}: the start position has only been there twice", backend.name);
+
+            play(game.as_mut(), &shuffle[3..]).expect(backend.name);
+            assert_eq!(game.joever(), Joever::Draw, "{}: the start position is there for the third time", backend.name);
+        }
+    }
+
+    #[test]
+    fn insufficient_material() {
+        let mut board = [[Piece::None; 8]; 8];
+        board[0][4] = Piece::WhiteKing;
+        board[7][4] = Piece::BlackKing;
+        assert!(super::insufficient_material(&board), "two kings can't mate");
+
+        board[0][2] = Piece::WhiteBishop;
+        board[7][5] = Piece::BlackBishop;
+        assert!(super::insufficient_material(&board), "bishops on squares of the same color can't mate");
+
+        board[7][2] = Piece::BlackBishop;
+        assert!(!super::insufficient_material(&board), "bishops on both colors can mate");
+
+        board[7][2] = Piece::None;
+        board[1][0] = Piece::WhitePawn;
+        assert!(!super::insufficient_material(&board), "a pawn can still promote");
+    }
+}
@@ -1,80 +1,362 @@
-use serde::Deserialize;
 use chess_network_protocol::*;
 
+use std::io;
 use std::net::{TcpListener, TcpStream};
-use std::sync::mpsc::{Sender, Receiver};
+use std::sync::mpsc::{Sender, Receiver, TryRecvError};
+use std::thread;
 
-use crate::{TcpToGame, GameToTcp};
-
-use local_ip_address::local_ip;
+use crate::{TcpToGame, GameToTcp, board_utils, extension, oposite_color, piece_color, cords_to_square, local_address};
+use crate::connection::{self, Received};
+use crate::transcript::Side;
+use crate::connection::Connection;
+use crate::extension::Extension;
 
+// object safe so the backends can be picked from backends::BACKENDS at runtime, new() is only for
+// code that knows the concrete backend
 pub trait UniversalGame {
     fn try_move(&mut self, m: Move) -> Result<(), String>;
     fn possible_moves(&mut self) -> Vec<Move>;
-    fn new() -> Self;
+    fn new() -> Self where Self: Sized;
     fn board(&self) -> [[Piece; 8]; 8];
     fn turn(&self) -> Color;
     fn joever(&self) -> Joever;
     fn features(&self) -> Vec<Features>;
+    // the moves played since new()
+    fn history(&self) -> &[Move];
+    // back to the start position
+    fn reset(&mut self);
+
+    // none of the backends can be cloned or take a move back, but every one of them can play the
+    // same moves again from the start
+    fn snapshot(&self) -> Vec<Move> {
+        self.history().to_vec()
+    }
+
+    // if the backend refuses a move of the snapshot the game stops just before it
+    fn restore(&mut self, snapshot: &[Move]) -> Result<(), String> {
+        self.reset();
+
+        for mv in snapshot {
+            self.try_move(*mv)?;
+        }
+
+        Ok(())
+    }
+
+    fn undo_last_move(&mut self) -> Result<(), String> {
+        let mut history = self.snapshot();
+
+        if history.pop().is_none() {
+            return Err("There is no move to take back".to_string());
+        }
+
+        self.restore(&history)
+    }
+
+    // moves for either color, used to show hints to the player that is waiting. backends only
+    // generate moves for the side to move, so the other side gets pseudo legal moves from the board
+    fn possible_moves_for(&mut self, color: &Color) -> Vec<Move> {
+        let generates = self.features().iter().any(|f| matches!(f, Features::PossibleMoveGeneration));
+
+        if *color == self.turn() && generates {
+            self.possible_moves()
+        }
+        else {
+            board_utils::pseudo_legal_moves(&self.board(), color)
+        }
+    }
 }
 
-pub fn run(sender: Sender<TcpToGame>, receiver: Receiver<GameToTcp>, mut game: impl UniversalGame) {
-    let mut draw = false;
+// the backend, and the result when the game ended in a way the backend doesn't know about, a
+// resignation or a draw
+struct Game {
+    backend: Box<dyn UniversalGame>,
+    result: Option<Joever>,
+}
+
+impl Game {
+    fn joever(&self) -> Joever {
+        match &self.result {
+            Some(result) => result.clone(),
+            None => self.backend.joever(),
+        }
+    }
+
+    fn try_move(&mut self, m: Move) -> Result<(), String> {
+        if self.result.is_some() {
+            return Err("The game is already over".to_string());
+        }
+
+        self.backend.try_move(m)
+    }
+
+    fn possible_moves(&mut self) -> Vec<Move> {
+        if self.result.is_some() {
+            return vec![];
+        }
+
+        self.backend.possible_moves()
+    }
+
+    fn possible_moves_for(&mut self, color: &Color) -> Vec<Move> {
+        self.backend.possible_moves_for(color)
+    }
 
-    let listener = TcpListener::bind(local_ip().unwrap().to_string() + ":8384").unwrap();
+    fn board(&self) -> [[Piece; 8]; 8] {
+        self.backend.board()
+    }
 
-    // accept connections and process them serially
-    let (stream, _addr) = listener.accept().unwrap();
-    let mut de = serde_json::Deserializer::from_reader(&stream);
+    fn turn(&self) -> Color {
+        self.backend.turn()
+    }
+
+    fn features(&self) -> Vec<Features> {
+        self.backend.features()
+    }
+
+    fn snapshot(&self) -> Vec<Move> {
+        self.backend.snapshot()
+    }
+
+    fn restore(&mut self, snapshot: &[Move]) -> Result<(), String> {
+        self.backend.restore(snapshot)
+    }
+
+    fn reset(&mut self) {
+        self.backend.reset();
+        self.result = None;
+    }
+}
+
+pub fn run(sender: Sender<TcpToGame>, receiver: Receiver<GameToTcp>, game: Box<dyn UniversalGame>) {
+    let address = local_address() + ":8384";
+
+    let listener = match TcpListener::bind(&address) {
+        Ok(listener) => listener,
+        Err(e) => {
+            let _ = sender.send(TcpToGame::Error { message: format!("Could not listen on {}: {}", address, e) });
+            return;
+        },
+    };
+
+    serve(listener, sender, receiver, game);
+}
+
+// the session on an already bound listener, the mock peer uses this on loopback
+pub fn serve(listener: TcpListener, sender: Sender<TcpToGame>, receiver: Receiver<GameToTcp>, backend: Box<dyn UniversalGame>) {
+    let stream = match accept(&listener, &receiver) {
+        Ok(Some(stream)) => stream,
+        // the gui cancelled, dropping the listener frees the port
+        Ok(None) => return,
+        Err(e) => {
+            let _ = sender.send(TcpToGame::Error { message: format!("Could not accept the client: {}", e) });
+            return;
+        },
+    };
+    let mut connection = Connection::new(stream, sender, receiver, Side::Server);
+    let mut game = Game { backend, result: None };
+
+    // until the client answers with its features it is assumed to handle everything the backend does
+    connection.offered = features(&game);
+    connection.features = game.features();
 
     //receive
-    let deserialized = ClientToServerHandshake::deserialize(&mut de).unwrap();
+    let deserialized: ClientToServerHandshake = match connection.receive_peer() {
+        Ok(Received::Peer(Ok(handshake))) => handshake,
+        Ok(Received::Peer(Err(e))) => {
+            connection.close(format!("The client's handshake isn't part of the protocol: {}", e));
+            return;
+        },
+        // the client or the gui left, nothing else can come before the handshake
+        _ => return,
+    };
+    let mut server_color = deserialized.server_color;
 
+    // a rematch keeps the connection, resets the game and swaps colors
+    loop {
+        handshake(&connection, &mut game, &server_color);
+
+        if !play(&mut connection, &mut game, &server_color) {
+            return;
+        }
+
+        // moves that still come in are answered with an error, the game is over
+        let rematch = connection.wait_for_rematch(|connection, value| {
+            if let Ok(message) = serde_json::from_value::<ClientToServer>(value) {
+                client_message(connection, &mut game, &server_color, message);
+            }
+        });
+
+        if !rematch {
+            return;
+        }
+
+        game.reset();
+        server_color = oposite_color(&server_color);
+    }
+}
+
+// waits for the client, None if the gui went back to the menu meanwhile
+fn accept(listener: &TcpListener, receiver: &Receiver<GameToTcp>) -> io::Result<Option<TcpStream>> {
+    listener.set_nonblocking(true)?;
+
+    loop {
+        match listener.accept() {
+            Ok((stream, _addr)) => {
+                stream.set_nonblocking(false)?;
+                return Ok(Some(stream));
+            },
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {},
+            Err(e) => return Err(e),
+        }
+
+        if let Err(TryRecvError::Disconnected) = receiver.try_recv() {
+            return Ok(None);
+        }
+
+        thread::sleep(connection::POLL_INTERVAL);
+    }
+}
+
+fn features(game: &Game) -> Vec<Features> {
+    let mut features = game.features();
+    features.append(&mut extension::features());
+    features
+}
+
+fn handshake(connection: &Connection, game: &mut Game, server_color: &Color) {
     let moves = game.possible_moves();
 
-    sender.send(TcpToGame::Handshake {
+    connection.sender.send(TcpToGame::Handshake {
         board: game.board(),
         moves: moves.clone(),
-        features: game.features(),
-        server_color: deserialized.server_color.clone(),
+        waiting_moves: game.possible_moves_for(&Color::Black),
+        features: connection.features.clone(),
+        server_color: server_color.clone(),
     }).unwrap();
 
     let handshake = ServerToClientHandshake {
-        features: game.features(),
+        features: features(game),
         board: game.board(),
         moves: moves,
         joever: Joever::Ongoing,
     };
 
     //send
-    serde_json::to_writer(&stream, &handshake).unwrap();
+    connection.write(&handshake).unwrap();
+}
+
+// plays until the game is over, by the backend's rules or because a side resigned or called a draw.
+// returns false if the client or the gui left before that
+fn play(connection: &mut Connection, game: &mut Game, server_color: &Color) -> bool {
+    // whose turn it is comes from the game, a takeback can give the turn back to either side
+    while game.joever() == Joever::Ongoing {
+        let connected = if game.turn() == *server_color {
+            make_move(connection, game, server_color)
+        }
+        else {
+            client_move(connection, game, server_color)
+        };
 
-    if &deserialized.server_color == &Color::White {
-        make_move(&sender, &receiver, &stream, &mut game, draw);
+        if !connected {
+            return false;
+        }
     }
 
+    true
+}
+
+// false if the client or the gui left
+fn client_move(connection: &mut Connection, game: &mut Game, server_color: &Color) -> bool {
     loop {
-        client_move(&sender, &receiver, &stream, &mut game);
+        //receive
+        let deserialized: ClientToServer = match connection.receive_peer() {
+            Ok(Received::Peer(Ok(message))) => message,
+            Ok(Received::Peer(Err(e))) => {
+                send_error(connection, game, format!("The message isn't part of the protocol: {}", e));
+                continue;
+            },
+            Ok(Received::Takeback(ours)) => {
+                take_back(connection, game, server_color, ours);
+                return true;
+            },
+            Ok(Received::Draw) => {
+                end(connection, game, Joever::Draw);
+                return true;
+            },
+            Ok(Received::Gui(_)) => continue,
+            Ok(Received::Closed) | Err(_) => return false,
+        };
 
-        make_move(&sender, &receiver, &stream, &mut game, draw);
+        if client_message(connection, game, server_color, deserialized) {
+            connection.moved(false);
+            return true;
+        }
     }
 }
 
-fn client_move(sender: &Sender<TcpToGame>, receiver: &Receiver<GameToTcp>, stream: &TcpStream, game: &mut impl UniversalGame) {
-    let mut de = serde_json::Deserializer::from_reader(stream);
+// takes back the last move of the side that asked, and the answer to it if there was one
+fn take_back(connection: &mut Connection, game: &mut Game, server_color: &Color, ours: bool) {
+    let asker = if ours { server_color.clone() } else { oposite_color(server_color) };
+    let plies = if game.turn() == asker { 2 } else { 1 };
+    let mut history = game.snapshot();
 
-    //receive
-    let deserialized = ClientToServer::deserialize(&mut de).unwrap();
+    let result = if history.len() < plies {
+        Err("There is no move to take back".to_string())
+    }
+    else {
+        history.truncate(history.len() - plies);
+        game.restore(&history)
+    };
+
+    if let Err(message) = result {
+        if ours {
+            connection.sender.send(TcpToGame::Error { message }).unwrap();
+        }
+        else {
+            send_error(connection, game, message);
+        }
+        return;
+    }
 
-    match deserialized {
+    let message = if plies == 1 { "The last move was taken back" } else { "The last two moves were taken back" };
+
+    connection.sender.send(TcpToGame::TakenBack(plies)).unwrap();
+    connection.sender.send(TcpToGame::Resync {
+        board: game.board(),
+        moves: game.possible_moves(),
+        joever: game.joever(),
+        turn: game.turn(),
+        waiting_moves: game.possible_moves_for(&oposite_color(&game.turn())),
+        message: message.to_string(),
+    }).unwrap();
+
+    // the client resyncs from the error like from any other
+    connection.write(&Extension::TakenBack(plies)).unwrap();
+    send_error(connection, game, message.to_string());
+}
+
+// answers a message from the client, returns true if it ended the client's turn: a move that got
+// played, a resignation or an agreed draw
+fn client_message(connection: &mut Connection, game: &mut Game, server_color: &Color, message: ClientToServer) -> bool {
+    if game.joever() != Joever::Ongoing {
+        send_error(connection, game, "The game is already over".to_string());
+        return false;
+    }
+
+    match message {
         ClientToServer::Move(move_made) => {
-            match game.try_move(move_made) {
+            let result = validate_move(game.backend.as_ref(), &oposite_color(server_color), &move_made)
+                .and_then(|()| game.try_move(move_made));
+
+            match result {
                 Ok(()) => {
                     let moves = game.possible_moves();
-                    sender.send(TcpToGame::State {
+                    connection.sender.send(TcpToGame::State {
                         board: game.board(),
                         moves: moves.clone(),
                         turn: game.turn(),
+                        waiting_moves: game.possible_moves_for(&oposite_color(&game.turn())),
                         move_made: move_made,
                         joever: game.joever(),
                     }).unwrap();
@@ -87,80 +369,244 @@ fn client_move(sender: &Sender<TcpToGame>, receiver: &Receiver<GameToTcp>, strea
                     };
 
                     //send
-                    serde_json::to_writer(stream, &state).unwrap();
+                    connection.write(&state).unwrap();
+                    true
                 }
                 Err(e) => {
-                    let state = ServerToClient::Error {
-                        board: game.board(),
-                        moves: game.possible_moves(),
-                        joever: Joever::Ongoing,
-                        message: e,
-                    };
-
-                    //send
-                    serde_json::to_writer(stream, &state).unwrap();
-
-                    client_move(sender, receiver, stream, game)
+                    send_error(connection, game, e);
+                    false
                 }
             }
         },
-        ClientToServer::Resign => { panic!("Resign not implemented") }
-        ClientToServer::Draw => { panic!("Draw not implemented") }
+        ClientToServer::Resign => {
+            end(connection, game, winner(server_color));
+            true
+        },
+        // an offer, or the answer to the server's
+        ClientToServer::Draw => {
+            if !connection.draw_offer_received() {
+                return false;
+            }
+
+            end(connection, game, Joever::Draw);
+            true
+        },
     }
 }
 
-fn make_move(sender: &Sender<TcpToGame>, receiver: &Receiver<GameToTcp>, stream: &TcpStream, game: &mut impl UniversalGame, mut draw: bool) {
-    match receiver.recv().unwrap() {
-        GameToTcp::Move(move_made) => {
-            match game.try_move(move_made) {
-                Ok(()) => {
-                    let moves = game.possible_moves();
-                    sender.send(TcpToGame::State {
-                        board: game.board(),
-                        moves: moves.clone(),
-                        turn: game.turn(),
-                        move_made: move_made,
-                        joever: game.joever(),
-                    }).unwrap();
-        
-                    let state = ServerToClient::State {
-                        board: game.board(),
-                        moves: moves,
-                        joever: game.joever(),
-                        move_made: move_made,
-                    };
-        
-                    //send
-                    serde_json::to_writer(stream, &state).unwrap();
+// a resignation or a draw, the backend doesn't know the game is over so the result is kept with it
+fn end(connection: &Connection, game: &mut Game, result: Joever) {
+    let board = game.board();
+    game.result = Some(result.clone());
+
+    if result == Joever::Draw {
+        connection.sender.send(TcpToGame::Draw { board, moves: vec![] }).unwrap();
+
+        //send
+        connection.write(&ServerToClient::Draw { board, moves: vec![] }).unwrap();
+    }
+    else {
+        connection.sender.send(TcpToGame::Resigned { board, joever: result.clone() }).unwrap();
+
+        //send
+        connection.write(&ServerToClient::Resigned { board, joever: result }).unwrap();
+    }
+}
+
+fn send_error(connection: &Connection, game: &mut Game, message: String) {
+    let state = ServerToClient::Error {
+        board: game.board(),
+        moves: game.possible_moves(),
+        joever: game.joever(),
+        message: message,
+    };
+
+    //send
+    connection.write(&state).unwrap();
+}
+
+// the rules every backend should enforce but that the server checks itself, so the other side
+// gets the same precise message whichever backend is running
+pub fn validate_move(game: &dyn UniversalGame, mover: &Color, mv: &Move) -> Result<(), String> {
+    if game.joever() != Joever::Ongoing {
+        return Err("The game is already over".to_string());
+    }
+
+    if game.turn() != *mover {
+        return Err(format!("It is {}'s turn, not {}'s", color_name(&game.turn()), color_name(mover)));
+    }
+
+    if mv.start_x > 7 || mv.start_y > 7 {
+        return Err("The move starts outside the board".to_string());
+    }
+    if mv.end_x > 7 || mv.end_y > 7 {
+        return Err("The move ends outside the board".to_string());
+    }
+
+    let square = cords_to_square(mv.start_x as f32, mv.start_y as f32);
+
+    match piece_color(&game.board()[mv.start_y][mv.start_x]) {
+        None => Err(format!("There is no piece on {}", square)),
+        Some(color) if color != *mover => Err(format!("The piece on {} is {}, you play {}", square, color_name(&color), color_name(mover))),
+        Some(_) => Ok(()),
+    }
+}
+
+fn color_name(color: &Color) -> &'static str {
+    match color {
+        Color::White => "White",
+        Color::Black => "Black",
+    }
+}
+
+// false if the client or the gui left
+fn make_move(connection: &mut Connection, game: &mut Game, server_color: &Color) -> bool {
+    loop {
+        let message = match connection.receive_any::<ClientToServer>() {
+            Ok(Received::Gui(message)) => message,
+            // it's not the client's turn, so this only gets an error back unless the client resigns or
+            // calls a draw
+            Ok(Received::Peer(Ok(message))) => {
+                client_message(connection, game, server_color, message);
+
+                if game.joever() != Joever::Ongoing {
+                    return true;
                 }
-                Err(message) => {
-                    sender.send(TcpToGame::Error { message }).unwrap();
-                    make_move(sender, receiver, stream, game, draw);
+                continue;
+            },
+            Ok(Received::Peer(Err(e))) => {
+                send_error(connection, game, format!("The message isn't part of the protocol: {}", e));
+                continue;
+            },
+            Ok(Received::Takeback(ours)) => {
+                take_back(connection, game, server_color, ours);
+                return true;
+            },
+            Ok(Received::Draw) => {
+                end(connection, game, Joever::Draw);
+                return true;
+            },
+            Ok(Received::Closed) | Err(_) => return false,
+        };
+
+        match message {
+            GameToTcp::Move(move_made) => {
+                let result = validate_move(game.backend.as_ref(), server_color, &move_made)
+                    .and_then(|()| game.try_move(move_made));
+
+                match result {
+                    Ok(()) => {
+                        let moves = game.possible_moves();
+                        connection.sender.send(TcpToGame::State {
+                            board: game.board(),
+                            moves: moves.clone(),
+                            turn: game.turn(),
+                            waiting_moves: game.possible_moves_for(&oposite_color(&game.turn())),
+                            move_made: move_made,
+                            joever: game.joever(),
+                        }).unwrap();
+
+                        let state = ServerToClient::State {
+                            board: game.board(),
+                            moves: moves,
+                            joever: game.joever(),
+                            move_made: move_made,
+                        };
+
+                        //send
+                        connection.write(&state).unwrap();
+                        connection.moved(true);
+                        return true;
+                    }
+                    Err(message) => {
+                        connection.sender.send(TcpToGame::Error { message }).unwrap();
+                    }
                 }
-            }
-        },
-        GameToTcp::Draw => {
-            let state = ServerToClient::Draw { 
-                board: game.board(), 
-                moves: game.possible_moves(),
-            };
+            },
+            // a rematch is only asked for after the game, chat, takebacks and draw offers are handled by
+            // the connection
+            GameToTcp::Rematch | GameToTcp::Chat(_) | GameToTcp::Takeback | GameToTcp::Draw | GameToTcp::DeclineDraw => {},
+            GameToTcp::Resign => {
+                end(connection, game, winner(&oposite_color(server_color)));
+                return true;
+            },
+        }
+    }
+}
+
+fn winner(color: &Color) -> Joever {
+    match color {
+        Color::White => Joever::White,
+        Color::Black => Joever::Black,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chess_network_protocol::*;
+
+    use crate::backends;
+    use crate::test_utils::{mv, play};
+
+    #[test]
+    fn undo() {
+        for backend in backends::BACKENDS {
+            let mut game = (backend.new)();
+            play(game.as_mut(), &[("e2", "e4"), ("e7", "e5")]).expect(backend.name);
+            let board = game.board();
+
+            play(game.as_mut(), &[("g1", "f3")]).expect(backend.name);
+            game.undo_last_move().expect(backend.name);
+
+            assert_eq!(game.board(), board, "{}: the board should be the one before the move", backend.name);
+            assert_eq!(game.turn(), Color::White, "{}: it should be white's turn again", backend.name);
+            assert_eq!(game.history(), [mv("e2", "e4"), mv("e7", "e5")], "{}: the move should be gone from the history", backend.name);
+
+            // and the same move can be played again
+            play(game.as_mut(), &[("g1", "f3")]).expect(backend.name);
+        }
+    }
 
-            //send
-            serde_json::to_writer(stream, &state).unwrap();
+    #[test]
+    fn undo_without_moves() {
+        for backend in backends::BACKENDS {
+            let mut game = (backend.new)();
 
-            draw = true;
+            assert!(game.undo_last_move().is_err(), "{}: there is nothing to undo in a new game", backend.name);
+            assert_eq!(game.board(), (backend.new)().board(), "{}: a failed undo shouldn't change the board", backend.name);
         }
-        GameToTcp::Resign => {
-            let draw = ServerToClient::Resigned { 
-                board: game.board(), 
-                joever: match game.turn() {
-                    Color::Black => Joever::White,
-                    Color::White => Joever::Black,
-                },
-            };
+    }
 
-            //send
-            serde_json::to_writer(stream, &draw).unwrap();
+    #[test]
+    fn undo_after_mate() {
+        for backend in backends::BACKENDS {
+            let mut game = (backend.new)();
+            play(game.as_mut(), &[("f2", "f3"), ("e7", "e5"), ("g2", "g4")]).expect(backend.name);
+            let board = game.board();
+
+            play(game.as_mut(), &[("d8", "h4")]).expect(backend.name);
+            game.undo_last_move().expect(backend.name);
+
+            assert_eq!(game.board(), board, "{}: the board should be the one before the mate", backend.name);
+            assert_eq!(game.joever(), Joever::Ongoing, "{}: the game should be ongoing again", backend.name);
+            assert_eq!(game.turn(), Color::Black, "{}: it should be black's turn again", backend.name);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn snapshot_restore() {
+        for backend in backends::BACKENDS {
+            let mut game = (backend.new)();
+            play(game.as_mut(), &[("e2", "e4"), ("e7", "e5")]).expect(backend.name);
+            let snapshot = game.snapshot();
+            let board = game.board();
+
+            play(game.as_mut(), &[("g1", "f3"), ("b8", "c6"), ("f1", "c4")]).expect(backend.name);
+            game.restore(&snapshot).expect(backend.name);
+
+            assert_eq!(game.board(), board, "{}: the board should be the one of the snapshot", backend.name);
+            assert_eq!(game.turn(), Color::White, "{}: it should be white's turn like in the snapshot", backend.name);
+            assert_eq!(game.history(), snapshot, "{}: the history should be the snapshot", backend.name);
+        }
+    }
+}
@@ -0,0 +1,27 @@
+use chess_network_protocol::*;
+
+use crate::server::UniversalGame;
+
+// helpers shared by the tests of the network threads, the backends and the rules
+
+// "e2" to (4, 1)
+fn square(name: &str) -> (usize, usize) {
+    let bytes = name.as_bytes();
+    ((bytes[0] - b'a') as usize, (bytes[1] - b'1') as usize)
+}
+
+pub fn mv(from: &str, to: &str) -> Move {
+    let (start_x, start_y) = square(from);
+    let (end_x, end_y) = square(to);
+
+    Move { start_x, start_y, end_x, end_y, promotion: Piece::None }
+}
+
+// plays the moves in order, Err names the one the backend refused
+pub fn play(game: &mut dyn UniversalGame, moves: &[(&str, &str)]) -> Result<(), String> {
+    for (from, to) in moves {
+        game.try_move(mv(from, to)).map_err(|e| format!("{}{} was refused: {}", from, to, e))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,113 @@
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write, BufRead, BufReader};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+// every message of a session, one json object per line, so a game that went wrong between two
+// machines can be replayed later. they go into ./transcripts, CHESS_TRANSCRIPTS=<dir> puts them
+// somewhere else and CHESS_TRANSCRIPTS=off turns them off
+
+// sessions started by this process, so two in the same millisecond still get their own file
+static SESSIONS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+    Server,
+    Client,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    // since the connection was made
+    pub seconds: f64,
+    pub from: Side,
+    pub message: Value,
+}
+
+pub struct Transcript {
+    file: File,
+    side: Side,
+    started: Instant,
+}
+
+impl Transcript {
+    // None if transcripts are turned off
+    pub fn create(side: Side) -> io::Result<Option<Self>> {
+        let directory = match directory() {
+            Some(directory) => directory,
+            None => return Ok(None),
+        };
+        let name = match side {
+            Side::Server => "server",
+            Side::Client => "client",
+        };
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let path = directory.join(format!("transcript-{}-{}-{}-{}.jsonl", name, millis, process::id(), SESSIONS.fetch_add(1, Ordering::Relaxed)));
+
+        fs::create_dir_all(&directory)?;
+
+        // never overwrite another session's transcript
+        let file = OpenOptions::new().write(true).create_new(true).open(path)?;
+
+        Ok(Some(Transcript {
+            file,
+            side,
+            started: Instant::now(),
+        }))
+    }
+
+    pub fn sent(&self, message: &Value) {
+        self.record(self.side, message);
+    }
+
+    pub fn received(&self, message: &Value) {
+        let from = match self.side {
+            Side::Server => Side::Client,
+            Side::Client => Side::Server,
+        };
+        self.record(from, message);
+    }
+
+    // a transcript that can't be written shouldn't end the game
+    fn record(&self, from: Side, message: &Value) {
+        let entry = Entry {
+            seconds: self.started.elapsed().as_secs_f64(),
+            from,
+            message: message.clone(),
+        };
+
+        if let Ok(line) = serde_json::to_string(&entry) {
+            let _ = writeln!(&self.file, "{}", line);
+        }
+    }
+}
+
+fn directory() -> Option<PathBuf> {
+    // the tests run sessions over loopback, their transcripts aren't worth keeping
+    if cfg!(test) {
+        return None;
+    }
+
+    match env::var("CHESS_TRANSCRIPTS") {
+        Ok(value) if value == "off" => None,
+        Ok(value) => Some(PathBuf::from(value)),
+        Err(_) => Some(PathBuf::from("transcripts")),
+    }
+}
+
+pub fn read(path: &str) -> io::Result<Vec<Entry>> {
+    BufReader::new(File::open(path)?)
+        .lines()
+        .filter(|line| !matches!(line, Ok(l) if l.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}